@@ -0,0 +1,390 @@
+use std::cmp::Reverse;
+use std::fs;
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use serde::{Serialize, Deserialize};
+use crate::filesystem::{FileInfo, FileSystem};
+use crate::group::DuplicateGroup;
+use crate::options::Keep;
+use crate::output;
+
+//One operation of an action on `target`, a copy of `survivor` which is kept
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActionStep {
+    pub survivor: PathBuf,
+    pub target: PathBuf,
+    //size hashed, the one of the group when known
+    pub size: u64,
+    //modification time of the target when planned, right after the hashing
+    pub modified: Option<SystemTime>,
+    //and the one of the survivor
+    pub survivor_modified: Option<SystemTime>,
+    //content hash of the group, when the comparison computed one
+    pub hash: Option<String>,
+    //hard link of an earlier target of the group, its space is only freed once, by that step
+    pub linked: bool,
+    //why the target is left for review instead of acted on (see Risks)
+    pub risk: Option<String>,
+}
+
+/*
+ * Steps to apply on a group of identical files: one regular file is kept, the first
+ * one or the one chosen by `keep`, and every other regular file becomes a target.
+ * Copies as deep (or shallow) as each other are told apart by the smallest path.
+ * With `homes`, the choice is made among the members under the first of them holding
+ * one (see regular_files).
+ * Members vanished since the hashing are left out.
+ * Symbolic links are listed in groups since hashing reads through them, but they are
+ * never the survivor (acting toward a link would alias whatever it points to) nor a
+ * target (they take no space). A group without regular file has no step.
+ * Files already sharing the survivor's inode are left alone too, as there is nothing
+ * to reclaim on them. Targets hard linked together all get a step, the first one
+ * accounting for the space.
+ */
+pub fn plan<S: FileSystem>(filesystem: &S, group: &DuplicateGroup, keep: Keep, homes: &[PathBuf]) -> Result<Vec<ActionStep>, Error> {
+    let mut files = regular_files(filesystem, group, keep, homes)?.into_iter();
+    let (survivor, survivor_metadata) = match files.next() {
+        Some(survivor) => survivor,
+        None => return Ok(Vec::new()),
+    };
+    let mut steps = Vec::new();
+    let mut targets : Vec<FileInfo> = Vec::new();
+    for (target, metadata) in files {
+        if survivor_metadata.same_inode(&metadata) {
+            continue
+        }
+        steps.push(ActionStep {
+            survivor: survivor.clone(),
+            target: target.clone(),
+            size: group.size.unwrap_or(metadata.len),
+            modified: metadata.modified,
+            survivor_modified: survivor_metadata.modified,
+            hash: group.hash.clone(),
+            linked: targets.iter().any(|t| t.same_inode(&metadata)),
+            risk: None,
+        });
+        targets.push(metadata);
+    }
+    Ok(steps)
+}
+
+//Copy of the group an action would keep, None when it has no regular file
+pub fn survivor<S: FileSystem>(filesystem: &S, group: &DuplicateGroup, keep: Keep, homes: &[PathBuf]) -> Result<Option<PathBuf>, Error> {
+    Ok(regular_files(filesystem, group, keep, homes)?.into_iter().next().map(|(f, _)| f.clone()))
+}
+
+/*
+ * Regular files of the group, the one to keep first. `homes` are canonical directories
+ * where the copy to keep belongs, in order of preference: the members under the first
+ * one holding any are the only candidates, `keep` then picks among them as it would
+ * among all the members. Without a member under any of them, all are candidates.
+ */
+fn regular_files<'a, S: FileSystem>(filesystem: &S, group: &'a DuplicateGroup, keep: Keep, homes: &[PathBuf]) -> Result<Vec<(&'a PathBuf, FileInfo)>, Error> {
+    let mut regular = Vec::new();
+    for file in group.files.iter() {
+        //A member gone since the hashing is neither kept nor acted on
+        let metadata = match filesystem.symlink_metadata(file) {
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            metadata => metadata?,
+        };
+        if metadata.is_file {
+            regular.push((file, metadata));
+        }
+    }
+    let mut candidates : Vec<usize> = (0..regular.len()).collect();
+    if !homes.is_empty() {
+        let canonical = regular.iter().map(|(f, _)| filesystem.canonicalize(f)).collect::<Result<Vec<PathBuf>, Error>>()?;
+        let home = homes.iter().find(|h| canonical.iter().any(|c| c.starts_with(h)));
+        if let Some(home) = home {
+            candidates.retain(|&i| canonical[i].starts_with(home));
+        }
+    }
+    let depth = |file: &PathBuf| file.components().count();
+    let kept = match keep {
+        Keep::First => candidates.first().copied(),
+        Keep::Deepest => candidates.iter().copied().min_by_key(|&i| (Reverse(depth(regular[i].0)), regular[i].0)),
+        Keep::Shallowest => candidates.iter().copied().min_by_key(|&i| (depth(regular[i].0), regular[i].0)),
+    };
+    if let Some(i) = kept {
+        let survivor = regular.remove(i);
+        regular.insert(0, survivor);
+    }
+    Ok(regular)
+}
+
+//Directories of the system, whose files are never acted on without review by default
+pub const SYSTEM_PATHS: &[&str] = &["/bin", "/boot", "/dev", "/etc", "/lib", "/lib32", "/lib64", "/opt", "/proc", "/sbin", "/sys", "/usr", "/var", "/Library", "/System"];
+
+/*
+ * Rules telling the targets that deserve a look before being acted on: files under one
+ * of `paths`, read-only files and files modified less than `recent` ago (a copy still
+ * being worked on). The others are safe to act on.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Risks {
+    pub paths: Vec<PathBuf>,
+    pub recent: Option<Duration>,
+}
+
+impl Default for Risks {
+    fn default() -> Risks {
+        Risks {
+            paths: SYSTEM_PATHS.iter().map(PathBuf::from).collect(),
+            recent: Some(Duration::from_secs(24 * 60 * 60)),
+        }
+    }
+}
+
+impl Risks {
+    //Why the target needs a review, None when it is safe
+    pub fn reason<S: FileSystem>(&self, filesystem: &S, target: &Path) -> Result<Option<String>, Error> {
+        let path = filesystem.canonicalize(target)?;
+        if let Some(prefix) = self.paths.iter().find(|p| path.starts_with(p)) {
+            return Ok(Some(format!("under {}", prefix.to_string_lossy())));
+        }
+        let metadata = filesystem.metadata(&path)?;
+        if metadata.readonly {
+            return Ok(Some(String::from("read-only")));
+        }
+        let age = metadata.modified.and_then(|m| SystemTime::now().duration_since(m).ok());
+        if let (Some(recent), Some(age)) = (self.recent, age) {
+            if age < recent {
+                return Ok(Some(format!("modified {}", output::format_age(age))));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/*
+ * Fails when the target or the survivor isn't the file the step was planned on anymore:
+ * written to since, replaced or gone. A changed survivor would put its new content in
+ * place of the target, a gone one would leave the target as the only copy.
+ */
+fn unchanged<S: FileSystem>(filesystem: &S, step: &ActionStep) -> Result<(), Error> {
+    for (path, modified) in [(&step.target, step.modified), (&step.survivor, step.survivor_modified)].iter() {
+        let metadata = filesystem.symlink_metadata(path)?;
+        if !metadata.is_file || metadata.len != step.size || metadata.modified != *modified {
+            return Err(Error::other(format!("{} changed since it was hashed, {} left alone", path.to_string_lossy(), step.target.to_string_lossy())));
+        }
+    }
+    Ok(())
+}
+
+/*
+ * Replace the target by a copy-on-write clone of the survivor.
+ * The clone is made next to the target then renamed over it, so the target is
+ * untouched when the filesystem can't clone: the error is then ErrorKind::Unsupported.
+ * A file already at the place of the clone is left alone, the step then fails.
+ */
+pub fn reflink<S: FileSystem>(filesystem: &S, step: &ActionStep) -> Result<(), Error> {
+    unchanged(filesystem, step)?;
+    let tmp = temporary_path(&step.target);
+    filesystem.clone_file(&step.survivor, &tmp)?;
+    let replaced = filesystem.copy_permissions(&step.target, &tmp).and_then(|_| filesystem.rename(&tmp, &step.target));
+    if replaced.is_err() {
+        let _ = filesystem.remove_file(&tmp);
+    }
+    replaced
+}
+
+/*
+ * Move the target to the trash of the platform (freedesktop trash, macOS Trash,
+ * Windows Recycle Bin), from where it can be restored.
+ * Fails with the reason on systems without any trash, the target is then untouched.
+ */
+pub fn trash<S: FileSystem>(filesystem: &S, step: &ActionStep) -> Result<(), Error> {
+    unchanged(filesystem, step)?;
+    filesystem.trash(&step.target)
+}
+
+fn temporary_path(target: &Path) -> PathBuf {
+    let name = target.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    target.with_file_name(format!(".{}.rustadup-tmp", name))
+}
+
+//Line of an action log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub action: String,
+    pub target: PathBuf,
+    pub survivor: PathBuf,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    //RFC 3339, UTC
+    pub timestamp: String,
+}
+
+/*
+ * Audit trail of the operations done on the files, one JSON object per line appended
+ * (and flushed) as soon as the operation succeeded, so an interrupted run keeps the
+ * record of everything it changed.
+ * A reflinked target kept its content and permissions, only its blocks are now shared
+ * with the survivor: copying the survivor over it undoes the sharing. A trashed target
+ * is restored from the trash.
+ */
+pub struct ActionLog {
+    out: BufWriter<fs::File>,
+}
+
+impl ActionLog {
+    pub fn open(path: &Path) -> Result<ActionLog, Error> {
+        let file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(ActionLog { out: BufWriter::new(file) })
+    }
+
+    pub fn record(&mut self, action: &str, step: &ActionStep) -> Result<(), Error> {
+        let entry = LogEntry {
+            action: String::from(action),
+            target: step.target.clone(),
+            survivor: step.survivor.clone(),
+            size: step.size,
+            hash: step.hash.clone(),
+            timestamp: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        };
+        serde_json::to_writer(&mut self.out, &entry).map_err(Error::other)?;
+        writeln!(self.out)?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::Memory;
+
+    fn group(files: &[&str]) -> DuplicateGroup {
+        DuplicateGroup::new(String::from("a"), Some(3), None, files.iter().map(PathBuf::from).collect())
+    }
+
+    fn step(filesystem: &Memory, files: &[&str]) -> ActionStep {
+        plan(filesystem, &group(files), Keep::First, &[]).unwrap().remove(0)
+    }
+
+    #[test]
+    fn reflink_replaces_the_target() {
+        let filesystem = Memory::new();
+        filesystem.add_file(Path::new("x/a"), b"abc");
+        filesystem.add_file(Path::new("y/a"), b"abc");
+        filesystem.set_readonly(Path::new("y/a"), true).unwrap();
+        let before = filesystem.metadata(Path::new("y/a")).unwrap();
+        let step = step(&filesystem, &["x/a", "y/a"]);
+        assert_eq!((step.survivor.as_path(), step.target.as_path()), (Path::new("x/a"), Path::new("y/a")));
+        reflink(&filesystem, &step).unwrap();
+        let after = filesystem.metadata(Path::new("y/a")).unwrap();
+        assert!(!after.same_inode(&before));
+        assert!(after.readonly);
+        assert_eq!(filesystem.content(Path::new("y/a")), Some(b"abc".to_vec()));
+        assert!(filesystem.content(Path::new("y/.a.rustadup-tmp")).is_none());
+    }
+
+    #[test]
+    fn trash_moves_the_target() {
+        let filesystem = Memory::new();
+        filesystem.add_file(Path::new("x/a"), b"abc");
+        filesystem.add_file(Path::new("y/a"), b"abc");
+        let step = step(&filesystem, &["x/a", "y/a"]);
+        trash(&filesystem, &step).unwrap();
+        assert_eq!(filesystem.trashed(), vec![PathBuf::from("y/a")]);
+        assert!(filesystem.content(Path::new("y/a")).is_none());
+        assert_eq!(filesystem.content(Path::new("x/a")), Some(b"abc".to_vec()));
+    }
+
+    #[test]
+    fn symbolic_links_are_never_kept_nor_acted_on() {
+        let filesystem = Memory::new();
+        filesystem.add_symlink(Path::new("l/a"), Path::new("x/a"));
+        filesystem.add_file(Path::new("x/a"), b"abc");
+        filesystem.add_file(Path::new("y/a"), b"abc");
+        let group = group(&["l/a", "x/a", "y/a"]);
+        assert_eq!(survivor(&filesystem, &group, Keep::First, &[]).unwrap(), Some(PathBuf::from("x/a")));
+        let steps = plan(&filesystem, &group, Keep::First, &[]).unwrap();
+        assert_eq!(steps.iter().map(|s| s.target.as_path()).collect::<Vec<_>>(), vec![Path::new("y/a")]);
+        //Kept under a home through the link, the link itself is left alone
+        let homes = [PathBuf::from("y")];
+        assert_eq!(survivor(&filesystem, &group, Keep::First, &homes).unwrap(), Some(PathBuf::from("y/a")));
+    }
+
+    #[test]
+    fn vanished_target_is_left_alone() {
+        let filesystem = Memory::new();
+        filesystem.add_file(Path::new("x/a"), b"abc");
+        filesystem.add_file(Path::new("y/a"), b"abc");
+        let step = step(&filesystem, &["x/a", "y/a"]);
+        filesystem.remove_file(Path::new("y/a")).unwrap();
+        assert_eq!(reflink(&filesystem, &step).unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(trash(&filesystem, &step).unwrap_err().kind(), ErrorKind::NotFound);
+        assert!(filesystem.content(Path::new("y/.a.rustadup-tmp")).is_none());
+        assert!(filesystem.trashed().is_empty());
+        //Nor planned on once gone
+        assert!(plan(&filesystem, &group(&["x/a", "y/a"]), Keep::First, &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reflink_leaves_an_existing_temporary_file() {
+        let filesystem = Memory::new();
+        filesystem.add_file(Path::new("x/a"), b"abc");
+        filesystem.add_file(Path::new("y/a"), b"abc");
+        filesystem.add_file(Path::new("y/.a.rustadup-tmp"), b"mine");
+        let step = step(&filesystem, &["x/a", "y/a"]);
+        assert!(reflink(&filesystem, &step).is_err());
+        assert_eq!(filesystem.content(Path::new("y/.a.rustadup-tmp")), Some(b"mine".to_vec()));
+        assert_eq!(filesystem.content(Path::new("y/a")), Some(b"abc".to_vec()));
+    }
+
+    #[test]
+    fn changed_target_is_left_alone() {
+        let filesystem = Memory::new();
+        filesystem.add_file(Path::new("x/a"), b"abc");
+        filesystem.add_file(Path::new("y/a"), b"abc");
+        let step = step(&filesystem, &["x/a", "y/a"]);
+        filesystem.set_modified(Path::new("y/a"), SystemTime::UNIX_EPOCH + Duration::from_secs(1)).unwrap();
+        assert!(trash(&filesystem, &step).is_err());
+        assert!(reflink(&filesystem, &step).is_err());
+        assert!(filesystem.trashed().is_empty());
+        assert!(filesystem.content(Path::new("y/.a.rustadup-tmp")).is_none());
+    }
+
+    #[test]
+    fn resized_target_is_left_alone() {
+        let filesystem = Memory::new();
+        filesystem.add_file(Path::new("x/a"), b"abc");
+        filesystem.add_file(Path::new("y/a"), b"abc");
+        let step = step(&filesystem, &["x/a", "y/a"]);
+        filesystem.add_file(Path::new("y/a"), b"abcd");
+        assert!(trash(&filesystem, &step).is_err());
+        assert!(filesystem.trashed().is_empty());
+    }
+
+    #[test]
+    fn changed_survivor_leaves_the_target_alone() {
+        let filesystem = Memory::new();
+        filesystem.add_file(Path::new("x/a"), b"abc");
+        filesystem.add_file(Path::new("y/a"), b"abc");
+        let step = step(&filesystem, &["x/a", "y/a"]);
+        filesystem.set_modified(Path::new("x/a"), SystemTime::UNIX_EPOCH + Duration::from_secs(1)).unwrap();
+        assert!(reflink(&filesystem, &step).is_err());
+        assert!(trash(&filesystem, &step).is_err());
+        filesystem.add_file(Path::new("x/a"), b"abcd");
+        assert!(reflink(&filesystem, &step).is_err());
+        assert!(trash(&filesystem, &step).is_err());
+        assert_eq!(filesystem.content(Path::new("y/a")), Some(b"abc".to_vec()));
+        assert!(filesystem.content(Path::new("y/.a.rustadup-tmp")).is_none());
+        assert!(filesystem.trashed().is_empty());
+    }
+
+    #[test]
+    fn vanished_survivor_leaves_the_target_alone() {
+        let filesystem = Memory::new();
+        filesystem.add_file(Path::new("x/a"), b"abc");
+        filesystem.add_file(Path::new("y/a"), b"abc");
+        let step = step(&filesystem, &["x/a", "y/a"]);
+        filesystem.remove_file(Path::new("x/a")).unwrap();
+        assert_eq!(reflink(&filesystem, &step).unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(trash(&filesystem, &step).unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(filesystem.content(Path::new("y/a")), Some(b"abc".to_vec()));
+        assert!(filesystem.trashed().is_empty());
+    }
+}
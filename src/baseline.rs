@@ -0,0 +1,38 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use crate::group::DuplicateGroup;
+use crate::output::Document;
+
+type GroupKey = (String, Option<u64>, Option<String>);
+
+/*
+ * Groups of a previous report, to only show what is new since then.
+ * A group is known when the previous report had a group with the same name, size and
+ * hash holding every one of its files. A group that gained a member is new again, one
+ * that only lost members isn't.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Baseline {
+    groups: HashMap<GroupKey, HashSet<PathBuf>>,
+}
+
+fn key(group: &DuplicateGroup) -> GroupKey {
+    (group.name.clone(), group.size, group.hash.clone())
+}
+
+impl Baseline {
+    pub fn new(document: &Document) -> Baseline {
+        let mut groups : HashMap<GroupKey, HashSet<PathBuf>> = HashMap::new();
+        for group in document.groups.iter() {
+            groups.entry(key(group)).or_default().extend(group.files.iter().cloned());
+        }
+        Baseline { groups }
+    }
+
+    pub fn is_known(&self, group: &DuplicateGroup) -> bool {
+        match self.groups.get(&key(group)) {
+            Some(files) => group.files.iter().all(|f| files.contains(f)),
+            None => false,
+        }
+    }
+}
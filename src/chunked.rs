@@ -0,0 +1,88 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{BufReader, Error};
+use std::path::PathBuf;
+use fastcdc::v2020::StreamCDC;
+use sha2::{Digest, Sha256};
+use walkdir::DirEntry;
+use crate::deadline::Deadline;
+use crate::find;
+use crate::group::DuplicateGroup;
+use crate::hash::Hash;
+use crate::throttle::{Throttle, ThrottledReader};
+
+//Bounds of the average chunk size accepted by FastCDC
+pub const AVERAGE_MIN: u64 = fastcdc::v2020::AVERAGE_MIN as u64;
+pub const AVERAGE_MAX: u64 = fastcdc::v2020::AVERAGE_MAX as u64;
+
+pub fn find_overlaps<I>(iter: I, average: u64, min_overlap: f64, throttle: &mut Throttle, deadline: &Deadline) -> Result<Vec<DuplicateGroup>, Error>
+where I: IntoIterator<Item= DirEntry>,
+{
+    find::collect(|emit| for_each_overlap(iter, average, min_overlap, throttle, deadline, emit))
+}
+
+/*
+ * Experimental partial overlap detection: every file is cut in content-defined chunks
+ * (FastCDC), each chunk gets a sha256, and pairs of files sharing chunks are reported
+ * with the fraction of the smaller file they share.
+ *
+ * Every byte of every file is read and hashed once, and the chunk digests of the whole
+ * tree are kept in memory. A chunk present in k files adds k² / 2 pairs to count, so
+ * trees with many common chunks (e.g. zeroed regions) get expensive.
+ */
+pub fn for_each_overlap<I, F>(iter: I, average: u64, min_overlap: f64, throttle: &mut Throttle, deadline: &Deadline, mut emit: F) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    let average = average.clamp(AVERAGE_MIN, AVERAGE_MAX) as u32;
+    let mut files : Vec<(PathBuf, u64)> = Vec::new();
+    let mut chunks : HashMap<(Hash, usize), Vec<usize>> = HashMap::new();
+    for entry in iter.into_iter() {
+        if deadline.expired() {
+            break
+        }
+        let size = match find::unless_vanished(entry.path(), entry.metadata())? {
+            Some(metadata) => metadata.len(),
+            None => continue,
+        };
+        if size == 0 {
+            continue
+        }
+        let index = files.len();
+        let reader = match find::unless_vanished(entry.path(), fs::File::open(entry.path()))? {
+            Some(file) => ThrottledReader::new(BufReader::new(file), throttle),
+            None => continue,
+        };
+        //A chunk repeated within a file is only shared once with another file
+        let mut seen = HashSet::new();
+        for chunk in StreamCDC::new(reader, average / 4, average, average * 4) {
+            let chunk = chunk?;
+            let key = (Sha256::digest(&chunk.data), chunk.length);
+            if seen.insert(key) {
+                chunks.entry(key).or_default().push(index);
+            }
+        }
+        files.push((entry.into_path(), size));
+    }
+
+    let mut shared : HashMap<(usize, usize), u64> = HashMap::new();
+    for ((_, length), owners) in chunks.iter().filter(|c| c.1.len() > 1) {
+        for (i, a) in owners.iter().enumerate() {
+            for b in owners[i + 1..].iter() {
+                *shared.entry((*a, *b)).or_default() += *length as u64;
+            }
+        }
+    }
+    let mut pairs : Vec<((usize, usize), f64)> = shared.into_iter()
+        .map(|((a, b), bytes)| ((a, b), 100.0 * bytes as f64 / files[a].1.min(files[b].1) as f64))
+        .filter(|p| p.1 >= min_overlap)
+        .collect();
+    pairs.sort_by(|x, y| y.1.total_cmp(&x.1));
+    for ((a, b), overlap) in pairs.into_iter() {
+        let name = files[a].0.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let mut group = DuplicateGroup::new(name, Some(files[a].1.min(files[b].1)), None, vec![files[a].0.clone(), files[b].0.clone()]);
+        group.overlap = Some(overlap);
+        emit(group)?;
+    }
+    Ok(())
+}
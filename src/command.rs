@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use sha2::{Digest, Sha256};
+use walkdir::DirEntry;
+use crate::deadline::Deadline;
+use crate::find::{self, HashOptions};
+use crate::group::DuplicateGroup;
+use crate::hash::Hash;
+use crate::throttle::Throttle;
+
+//Exit status of a shell that couldn't find or run the command
+const NOT_RUNNABLE: &[i32] = &[126, 127];
+
+//The shell running `command` with the path as its last argument
+#[cfg(unix)]
+fn shell(command: &str, path: &Path) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(format!("{} \"$@\"", command)).arg("sh").arg(path);
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str, path: &Path) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command).arg(path);
+    shell
+}
+
+/*
+ * Sha256 of what `command` prints on its standard output for the file, given as its
+ * last argument. None when it fails on the file, which is then warned about; a command
+ * the shell can't run at all fails the whole comparison instead of every file.
+ */
+pub fn output_hash(command: &str, path: &Path) -> Result<Option<Hash>, Error> {
+    let output = shell(command, path).stdin(Stdio::null()).output()?;
+    if output.status.code().is_some_and(|c| NOT_RUNNABLE.contains(&c)) {
+        let reason = String::from_utf8_lossy(&output.stderr);
+        return Err(Error::new(ErrorKind::NotFound, format!("--compare-cmd {} can't be run: {}", command, reason.trim())));
+    }
+    if !output.status.success() {
+        let reason = String::from_utf8_lossy(&output.stderr);
+        eprintln!("warning: --compare-cmd failed on {} ({}): {}", path.to_string_lossy(), output.status, reason.trim());
+        return Ok(None);
+    }
+    Ok(Some(Sha256::digest(&output.stdout)))
+}
+
+pub fn find_by_command<I>(iter: I, command: &str, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline) -> Result<Vec<DuplicateGroup>, Error>
+where I: IntoIterator<Item= DirEntry>,
+{
+    find::collect(|emit| for_each_by_command(iter, command, options, throttle, deadline, emit))
+}
+
+/*
+ * Groups of files of the same name that `command` prints the same output for, an
+ * equality defined outside (a normalizer, a pretty-printer, a metadata stripper).
+ * Sizes aren't compared, two equal files in this sense rarely have the same one.
+ * The command runs once for every file sharing its name with another one: a process
+ * each, much slower than hashing. Its reads are accounted in the throttle once each
+ * file is done.
+ */
+pub fn for_each_by_command<I, F>(iter: I, command: &str, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline, mut emit: F) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    let mut names : HashMap<String, Vec<(DirEntry, u64)>> = HashMap::new();
+    for entry in iter.into_iter() {
+        let f_size = match find::unless_vanished(entry.path(), entry.metadata())? {
+            Some(metadata) => metadata.len(),
+            None => continue,
+        };
+        if options.skips(f_size) {
+            continue
+        }
+        names.entry(options.key_name(&entry)).or_default().push((entry, f_size));
+    }
+    for (name, files) in names.into_iter() {
+        if files.len() < 2 && !options.unique {
+            continue
+        }
+        let mut hashes : HashMap<Hash, Vec<PathBuf>> = HashMap::new();
+        let mut complete = true;
+        for (entry, f_size) in files.into_iter() {
+            if deadline.expired() {
+                complete = false;
+                break
+            }
+            if let Some(f_hash) = output_hash(command, entry.path())? {
+                hashes.entry(f_hash).or_default().push(entry.into_path());
+            }
+            throttle.consume(f_size as usize);
+        }
+        if complete || !options.unique {
+            for (f_hash, files) in hashes.into_iter().filter(|e| (e.1.len() == 1) == options.unique) {
+                emit(DuplicateGroup::new(name.clone(), None, Some(format!("{:x}", f_hash)), files))?;
+            }
+        }
+        if !complete {
+            break
+        }
+    }
+    Ok(())
+}
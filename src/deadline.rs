@@ -0,0 +1,38 @@
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+/*
+ * Point in time after which scanning and hashing stop.
+ * Once expired it stays expired, so every phase sees the same answer.
+ */
+pub struct Deadline {
+    at: Option<Instant>,
+    expired: Cell<bool>,
+}
+
+impl Deadline {
+    pub fn new(timeout: Option<Duration>) -> Deadline {
+        Deadline {
+            at: timeout.map(|t| Instant::now() + t),
+            expired: Cell::new(false),
+        }
+    }
+
+    pub fn expired(&self) -> bool {
+        if !self.expired.get() && self.at.is_some_and(|at| Instant::now() >= at) {
+            self.expired.set(true);
+        }
+        self.expired.get()
+    }
+
+    //Expire now, stopping every phase as a timeout would
+    pub fn expire(&self) {
+        self.expired.set(true);
+    }
+}
+
+impl Default for Deadline {
+    fn default() -> Deadline {
+        Deadline::new(None)
+    }
+}
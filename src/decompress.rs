@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read};
+use std::path::Path;
+use flate2::read::MultiGzDecoder;
+use sha2::{Digest, Sha256};
+use walkdir::DirEntry;
+use crate::deadline::Deadline;
+use crate::find::{self, HashOptions};
+use crate::group::DuplicateGroup;
+use crate::hash::Hash;
+use crate::throttle::{Throttle, ThrottledReader};
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+//Compressed formats, recognized by the last extension of the name
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+//Compression of a file going by its name, along the name it has once decompressed
+pub fn compression(name: &str) -> Option<(Compression, &str)> {
+    let extensions = find::extension(name)?;
+    let last = extensions.rsplit('.').next().unwrap_or_default();
+    let compression = match last {
+        "gz" => Compression::Gzip,
+        "zst" => Compression::Zstd,
+        _ => return None,
+    };
+    Some((compression, &name[..name.len() - last.len() - 1]))
+}
+
+//First bytes of a zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/*
+ * Reader of a saved report, decompressed when the file starts as a zstd frame does,
+ * whatever its name: reports written through `| zstd` read back like plain ones.
+ */
+pub fn open_report(path: &Path) -> Result<Box<dyn Read>, Error> {
+    let mut file = BufReader::new(fs::File::open(path)?);
+    if file.fill_buf()?.starts_with(&ZSTD_MAGIC) {
+        Ok(Box::new(zstd::stream::read::Decoder::with_buffer(file)?))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+fn is_compressed(path: &Path) -> bool {
+    path.file_name().is_some_and(|n| compression(&n.to_string_lossy()).is_some())
+}
+
+/*
+ * Sha256 and size of the content of the file, decompressed on the fly when its name
+ * says it is compressed. The read rate limit applies to the bytes read from disk.
+ * A compressed file that can't be decoded fails with InvalidData.
+ */
+pub fn hash_file(path: &Path, throttle: &mut Throttle) -> Result<(Hash, u64), Error> {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let file = ThrottledReader::new(fs::File::open(path)?, throttle);
+    let (mut reader, compressed) : (Box<dyn Read + '_>, bool) = match compression(&name) {
+        Some((Compression::Gzip, _)) => (Box::new(MultiGzDecoder::new(file)), true),
+        Some((Compression::Zstd, _)) => (Box::new(zstd::stream::read::Decoder::new(file)?), true),
+        None => (Box::new(file), false),
+    };
+    let mut sh = Sha256::default();
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut size = 0;
+    loop {
+        let n = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) if compressed => return Err(Error::new(ErrorKind::InvalidData, format!("{} can't be decompressed: {}", path.to_string_lossy(), e))),
+            Err(e) => return Err(e),
+        };
+        sh.update(&buffer[..n]);
+        size += n as u64;
+    }
+    Ok((sh.finalize(), size))
+}
+
+pub fn find_by_decompressed<I>(iter: I, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline) -> Result<Vec<DuplicateGroup>, Error>
+where I: IntoIterator<Item= DirEntry>,
+{
+    find::collect(|emit| for_each_by_decompressed(iter, options, throttle, deadline, emit))
+}
+
+/*
+ * Same comparison as find::for_each_by_hashes, on the decompressed content of the
+ * .gz and .zst files: `file.txt` and `file.txt.gz` holding the same text are a group.
+ * The name compared has the compression extension left out and the size is the one
+ * of the decompressed content. As that size is only known once decompressed, buckets
+ * holding a compressed file are hashed whole; the others still skip the lone sizes.
+ * Files that can't be decompressed are warned about and passed over.
+ */
+pub fn for_each_by_decompressed<I, F>(iter: I, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline, mut emit: F) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    let mut filenames : HashMap<String, Vec<(DirEntry, u64)>> = HashMap::new();
+    for entry in iter.into_iter() {
+        let f_name = options.key_name(&entry);
+        let f_name = compression(&f_name).map(|c| String::from(c.1)).unwrap_or(f_name);
+        let f_size = match find::unless_vanished(entry.path(), entry.metadata())? {
+            Some(metadata) => metadata.len(),
+            None => continue,
+        };
+        if options.skips(f_size) {
+            continue
+        }
+        filenames.entry(f_name).or_default().push((entry, f_size));
+    }
+    for (name, mut files) in filenames.into_iter() {
+        if !options.unique && !files.iter().any(|f| is_compressed(f.0.path())) {
+            let mut sizes : HashMap<u64, usize> = HashMap::new();
+            for f in files.iter() {
+                *sizes.entry(f.1).or_default() += 1;
+            }
+            files.retain(|f| sizes[&f.1] > 1);
+        }
+        if files.len() < 2 && !options.unique {
+            continue
+        }
+        let mut hashes : HashMap<(Hash, u64), Vec<DirEntry>> = HashMap::new();
+        let mut complete = true;
+        for (entry, _) in files.into_iter() {
+            if deadline.expired() {
+                complete = false;
+                break
+            }
+            let key = match find::unless_vanished(entry.path(), find::retrying(options.retries, throttle, |throttle| hash_file(entry.path(), throttle))) {
+                Ok(Some(key)) => key,
+                Ok(None) => continue,
+                Err(e) if e.kind() == ErrorKind::InvalidData => {
+                    eprintln!("warning: {}", e);
+                    continue
+                },
+                Err(e) => return Err(e),
+            };
+            hashes.entry(key).or_default().push(entry);
+        }
+        //An interrupted bucket may still hold unhashed copies, only its duplicates are certain
+        if complete || !options.unique {
+            for ((f_hash, size), files) in hashes.into_iter().filter(|e| (e.1.len() == 1) == options.unique) {
+                let files : Vec<_> = files.into_iter().map(DirEntry::into_path).collect();
+                let mut group = DuplicateGroup::new(name.clone(), Some(size), Some(format!("{:x}", f_hash)), Vec::new());
+                group.compressed = files.iter().filter(|f| is_compressed(f)).cloned().collect();
+                group.files = files;
+                emit(group)?;
+            }
+        }
+        if !complete {
+            break
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+use crate::deadline::Deadline;
+use crate::find;
+use crate::fuzzy;
+use crate::group::DuplicateGroup;
+use crate::hash::{self, Hash};
+use crate::options::Options;
+use crate::run;
+use crate::throttle::Throttle;
+
+//Content-based comparison of two trees
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TreeDiff {
+    //contents found in both trees, with every copy of each side
+    pub both: Vec<DuplicateGroup>,
+    //files whose content is nowhere in the other tree
+    pub only_a: Vec<PathBuf>,
+    pub only_b: Vec<PathBuf>,
+    //the timeout stopped the comparison, the lists are partial
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub timed_out: bool,
+}
+
+/*
+ * Files of `a` and `b` are matched on their content alone, wherever they are and
+ * whatever their name. Only the sizes found on both sides are hashed, a file with a
+ * size the other tree doesn't have goes straight to its side's list. A size with one big
+ * file on each side is settled by comparing the two (see fuzzy::compare).
+ * The mode of the options is ignored, their filters, read rate and timeout apply.
+ */
+pub fn diff(options: &Options, a: &Path, b: &Path) -> Result<TreeDiff, Error> {
+    let deadline = Deadline::new(options.timeout);
+    let mut throttle = Throttle::new(options.max_read_rate);
+    //Both sides of each size, a first then b
+    let mut sizes : HashMap<u64, (Vec<PathBuf>, Vec<PathBuf>)> = HashMap::new();
+    for (root, side) in [(a, 0), (b, 1)] {
+        let roots = [root.to_path_buf()];
+        for entry in run::files(&options.filters, &roots, &deadline) {
+            let size = match find::unless_vanished(entry.path(), entry.metadata())? {
+                Some(metadata) => metadata.len(),
+                None => continue,
+            };
+            let files = sizes.entry(size).or_default();
+            let files = if side == 0 { &mut files.0 } else { &mut files.1 };
+            files.push(entry.into_path());
+        }
+    }
+    let mut diff = TreeDiff::default();
+    for (size, (in_a, in_b)) in sizes.into_iter() {
+        if in_a.is_empty() || in_b.is_empty() {
+            diff.only_a.extend(in_a);
+            diff.only_b.extend(in_b);
+            continue
+        }
+        //A lone pair of big files is compared at once, and only hashed for its group when identical
+        if in_a.len() == 1 && in_b.len() == 1 && fuzzy::parallel(size, &throttle) {
+            match fuzzy::compare(&in_a[0], &in_b[0], &mut throttle)? {
+                Some(None) => {
+                    if let Some(f_hash) = find::unless_vanished(&in_a[0], hash::hash_file(&in_a[0], &mut throttle))? {
+                        let name = in_a[0].file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+                        diff.both.push(DuplicateGroup::new(name, Some(size), Some(format!("{:x}", f_hash)), vec![in_a[0].clone(), in_b[0].clone()]));
+                        continue
+                    }
+                },
+                found => {
+                    //Differing, or one of them vanished
+                    let exists = |f: &PathBuf| found.is_some() || f.exists();
+                    diff.only_a.extend(in_a.into_iter().filter(exists));
+                    diff.only_b.extend(in_b.into_iter().filter(exists));
+                    continue
+                },
+            }
+        }
+        let mut hashes : HashMap<Hash, (Vec<PathBuf>, Vec<PathBuf>)> = HashMap::new();
+        for (side, files) in [(0, in_a), (1, in_b)] {
+            for file in files.into_iter() {
+                if let Some(f_hash) = find::unless_vanished(&file, hash::hash_file(&file, &mut throttle))? {
+                    let hashed = hashes.entry(f_hash).or_default();
+                    let hashed = if side == 0 { &mut hashed.0 } else { &mut hashed.1 };
+                    hashed.push(file);
+                }
+            }
+        }
+        for (f_hash, (in_a, in_b)) in hashes.into_iter() {
+            if in_a.is_empty() || in_b.is_empty() {
+                diff.only_a.extend(in_a);
+                diff.only_b.extend(in_b);
+                continue
+            }
+            let name = in_a[0].file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            let files = in_a.into_iter().chain(in_b).collect();
+            diff.both.push(DuplicateGroup::new(name, Some(size), Some(format!("{:x}", f_hash)), files));
+        }
+        if deadline.expired() {
+            break
+        }
+    }
+    diff.only_a.sort();
+    diff.only_b.sort();
+    diff.both.sort_by(|x, y| x.files.cmp(&y.files));
+    diff.timed_out = deadline.expired();
+    Ok(diff)
+}
@@ -0,0 +1,143 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use walkdir::DirEntry;
+use sha2::{Digest, Sha256};
+use crate::deadline::Deadline;
+use crate::find::{self, HashOptions};
+use crate::group::DuplicateGroup;
+use crate::hash::{self, Hash};
+use crate::throttle::Throttle;
+
+//What a directory holds, as far as the walk saw it
+#[derive(Default)]
+struct Directory {
+    //kind ('f' or 'd'), name and digest of every file and subdirectory
+    entries: Vec<(char, String, Hash)>,
+    size: u64,
+    files: u64,
+    //a file under it couldn't be hashed, its content isn't fully known
+    incomplete: bool,
+}
+
+fn name_of(path: &Path) -> String {
+    path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_else(|| path.to_string_lossy().into_owned())
+}
+
+//Digest of a directory: its entries sorted by name, each as its kind, name and digest
+fn digest(entries: &mut [(char, String, Hash)]) -> Hash {
+    entries.sort_by(|a, b| a.1.cmp(&b.1));
+    let mut sh = Sha256::default();
+    for (kind, name, digest) in entries.iter() {
+        sh.update(format!("{} {}\0{:x}\n", kind, name, digest));
+    }
+    sh.finalize()
+}
+
+pub fn find_with_directories<I>(iter: I, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline) -> Result<Vec<DuplicateGroup>, Error>
+where I: IntoIterator<Item= DirEntry>,
+{
+    find::collect(|emit| for_each_with_directories(iter, options, throttle, deadline, emit))
+}
+
+/*
+ * Duplicate directories and duplicate files from one hashing of every file. A
+ * directory's digest covers the names and digests of its files and subdirectories, so
+ * two directories have the same one when they hold the same tree; directories without
+ * a file under them are left out, as are the ones under which a file vanished.
+ *
+ * The directory groups come first, the topmost ones only: a group whose members all sit
+ * in members of another group says nothing more. The file groups follow, except the
+ * ones whose members are all inside the directories reported.
+ *
+ * Nothing is emitted before the whole tree is hashed. After a timeout the directories
+ * walked so far may be partial and none is reported, only the files.
+ */
+pub fn for_each_with_directories<I, F>(iter: I, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline, mut emit: F) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    let mut files : HashMap<(String, u64, Hash), Vec<PathBuf>> = HashMap::new();
+    let mut directories : HashMap<PathBuf, Directory> = HashMap::new();
+    //Depth of every directory under its root, to fold them from the deepest up
+    let mut depths : HashMap<PathBuf, usize> = HashMap::new();
+    for entry in iter.into_iter() {
+        if deadline.expired() {
+            break
+        }
+        //Directories up to the root of the walk, which is at the depth of the entry above it
+        let ancestors : Vec<&Path> = entry.path().ancestors().skip(1).take(entry.depth()).collect();
+        for (i, ancestor) in ancestors.iter().enumerate() {
+            depths.entry(ancestor.to_path_buf()).or_insert(entry.depth() - 1 - i);
+        }
+        let hashed = match find::unless_vanished(entry.path(), entry.metadata())? {
+            Some(metadata) => find::unless_vanished(entry.path(), find::retrying(options.retries, throttle, |throttle| hash::hash_file(entry.path(), throttle)))?.map(|h| (metadata.len(), h)),
+            None => None,
+        };
+        let (f_size, f_hash) = match hashed {
+            Some(hashed) => hashed,
+            None => {
+                for ancestor in ancestors.iter() {
+                    directories.entry(ancestor.to_path_buf()).or_default().incomplete = true;
+                }
+                continue
+            },
+        };
+        for ancestor in ancestors.iter() {
+            let directory = directories.entry(ancestor.to_path_buf()).or_default();
+            directory.size += f_size;
+            directory.files += 1;
+        }
+        if let Some(parent) = ancestors.first() {
+            directories.entry(parent.to_path_buf()).or_default().entries.push(('f', name_of(entry.path()), f_hash));
+        }
+        files.entry((options.key_name(&entry), f_size, f_hash)).or_default().push(entry.path().to_path_buf());
+    }
+
+    let mut reported : HashSet<PathBuf> = HashSet::new();
+    if !deadline.expired() {
+        let mut order : Vec<(PathBuf, usize)> = depths.into_iter().collect();
+        order.sort_by_key(|d| std::cmp::Reverse(d.1));
+        let mut digests : HashMap<Hash, Vec<PathBuf>> = HashMap::new();
+        for (path, depth) in order.into_iter() {
+            let mut directory = directories.remove(&path).unwrap_or_default();
+            let d_hash = digest(&mut directory.entries);
+            if depth > 0 {
+                if let Some(parent) = path.parent() {
+                    let up = directories.entry(parent.to_path_buf()).or_default();
+                    up.entries.push(('d', name_of(&path), d_hash));
+                }
+            }
+            //Kept aside for the size and count once grouped
+            if !directory.incomplete && directory.files > 0 {
+                digests.entry(d_hash).or_default().push(path.clone());
+            }
+            directories.insert(path, Directory { entries: Vec::new(), ..directory });
+        }
+        let groups : Vec<(Hash, Vec<PathBuf>)> = digests.into_iter().filter(|e| e.1.len() > 1).collect();
+        let duplicated : HashSet<&Path> = groups.iter().flat_map(|g| g.1.iter().map(PathBuf::as_path)).collect();
+        let mut topmost = Vec::new();
+        for (d_hash, members) in groups.iter() {
+            if members.iter().all(|m| m.parent().is_some_and(|p| duplicated.contains(p))) {
+                continue
+            }
+            topmost.push((d_hash, members));
+        }
+        for (d_hash, members) in topmost.into_iter() {
+            let directory = &directories[&members[0]];
+            let mut group = DuplicateGroup::new(name_of(&members[0]), Some(directory.size), Some(format!("{:x}", d_hash)), members.clone());
+            group.directory_files = Some(directory.files);
+            reported.extend(members.iter().cloned());
+            emit(group)?;
+        }
+    }
+
+    let inside = |file: &Path| file.ancestors().skip(1).any(|a| reported.contains(a));
+    for ((name, size, f_hash), members) in files.into_iter().filter(|e| e.1.len() > 1) {
+        if members.iter().all(|m| inside(m)) {
+            continue
+        }
+        emit(DuplicateGroup::new(name, Some(size), Some(format!("{:x}", f_hash)), members))?;
+    }
+    Ok(())
+}
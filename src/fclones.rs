@@ -0,0 +1,53 @@
+use std::io::{Error, Write};
+use std::time::SystemTime;
+use crate::group::DuplicateGroup;
+use crate::size::format_size;
+
+//Version of fclones whose text report this mirrors, `fclones remove` and `link` read it back
+const FCLONES_VERSION: &str = "0.34.0";
+
+//A path as fclones writes it: as is when the shell wouldn't split it, single quoted otherwise
+fn quote(path: &str) -> String {
+    let plain = !path.is_empty() && path.chars().all(|c| c.is_ascii_alphanumeric() || "/._-+,:@%=".contains(c));
+    if plain {
+        String::from(path)
+    } else {
+        format!("'{}'", path.replace('\'', "'\\''"))
+    }
+}
+
+//Timestamp in the format of the reports, e.g. 2024-03-05 13:45:12.123 +0000
+fn timestamp() -> String {
+    let time = humantime::format_rfc3339_millis(SystemTime::now()).to_string();
+    format!("{} +0000", time.trim_end_matches('Z').replacen('T', " ", 1))
+}
+
+/*
+ * Report in the default text format of `fclones group`, so the groups can be handed
+ * to the fclones actions: a header with the totals, then for each group its hash,
+ * file size and count followed by its members, one per line.
+ * fclones trusts the groups as identical files, which only h groups on whole hashes are.
+ */
+pub fn write_report<W: Write>(out: &mut W, groups: &[DuplicateGroup]) -> Result<(), Error> {
+    let command : Vec<String> = std::env::args().map(|a| quote(&a)).collect();
+    let base = std::env::current_dir()?;
+    let files : usize = groups.iter().map(|g| g.files.len()).sum();
+    let total : u64 = groups.iter().map(|g| g.size.unwrap_or(0) * g.files.len() as u64).sum();
+    let redundant_files : usize = groups.iter().map(|g| g.files.len().saturating_sub(1)).sum();
+    let redundant : u64 = groups.iter().map(|g| g.size.unwrap_or(0) * g.files.len().saturating_sub(1) as u64).sum();
+    writeln!(out, "# Report by fclones {}", FCLONES_VERSION)?;
+    writeln!(out, "# Timestamp: {}", timestamp())?;
+    writeln!(out, "# Command: {}", command.join(" "))?;
+    writeln!(out, "# Base dir: {}", quote(&base.to_string_lossy()))?;
+    writeln!(out, "# Total: {} B ({}) in {} files in {} groups", total, format_size(total), files, groups.len())?;
+    writeln!(out, "# Redundant: {} B ({}) in {} files", redundant, format_size(redundant), redundant_files)?;
+    writeln!(out, "# Missing: 0 B (0B) in 0 files")?;
+    for group in groups.iter() {
+        let size = group.size.unwrap_or(0);
+        writeln!(out, "{}, {} B ({}) * {}:", group.hash.as_deref().unwrap_or_default(), size, format_size(size), group.files.len())?;
+        for f in group.files.iter() {
+            writeln!(out, "    {}", quote(&f.to_string_lossy()))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,378 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Cursor, Error, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+//What the actions need to know of a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileInfo {
+    pub len: u64,
+    //a regular file, not a directory nor a symbolic link
+    pub is_file: bool,
+    //device and inode numbers, None where they aren't known
+    pub inode: Option<(u64, u64)>,
+    pub readonly: bool,
+    pub modified: Option<SystemTime>,
+}
+
+impl FileInfo {
+    //Two names of the same file, never true where inodes aren't known
+    pub fn same_inode(&self, other: &FileInfo) -> bool {
+        self.inode.is_some() && self.inode == other.inode
+    }
+}
+
+/*
+ * Operations of the actions on the files, planning included. Disk does them for real,
+ * Memory on a tree held in memory, so what an action would do to a tree can be checked
+ * without touching one.
+ */
+pub trait FileSystem {
+    //Information on the path itself, a symbolic link isn't followed
+    fn symlink_metadata(&self, path: &Path) -> Result<FileInfo, Error>;
+    fn metadata(&self, path: &Path) -> Result<FileInfo, Error>;
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf, Error>;
+    //Content of the file, through the symbolic links
+    fn open(&self, path: &Path) -> Result<Box<dyn Read>, Error>;
+    //Another name for the file at existing, link mustn't exist
+    fn hard_link(&self, existing: &Path, link: &Path) -> Result<(), Error>;
+    //Copy-on-write clone of source at destination, which mustn't exist; ErrorKind::Unsupported when the filesystem can't.
+    //Nothing is left at destination on failure, and a file already there is untouched
+    fn clone_file(&self, source: &Path, destination: &Path) -> Result<(), Error>;
+    fn copy_permissions(&self, from: &Path, to: &Path) -> Result<(), Error>;
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), Error>;
+    fn remove_file(&self, path: &Path) -> Result<(), Error>;
+    //Move to the trash of the platform, from where it can be restored
+    fn trash(&self, path: &Path) -> Result<(), Error>;
+}
+
+//The filesystems of the machine
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Disk;
+
+#[cfg(unix)]
+fn inode(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+fn info(metadata: fs::Metadata) -> FileInfo {
+    FileInfo {
+        len: metadata.len(),
+        is_file: metadata.file_type().is_file(),
+        inode: inode(&metadata),
+        readonly: metadata.permissions().readonly(),
+        modified: metadata.modified().ok(),
+    }
+}
+
+impl FileSystem for Disk {
+    fn symlink_metadata(&self, path: &Path) -> Result<FileInfo, Error> {
+        fs::symlink_metadata(path).map(info)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileInfo, Error> {
+        fs::metadata(path).map(info)
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf, Error> {
+        fs::canonicalize(path)
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn Read>, Error> {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+
+    fn hard_link(&self, existing: &Path, link: &Path) -> Result<(), Error> {
+        fs::hard_link(existing, link)
+    }
+
+    fn clone_file(&self, source: &Path, destination: &Path) -> Result<(), Error> {
+        clone_file(source, destination)
+    }
+
+    fn copy_permissions(&self, from: &Path, to: &Path) -> Result<(), Error> {
+        fs::set_permissions(to, fs::metadata(from)?.permissions())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), Error> {
+        fs::rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<(), Error> {
+        fs::remove_file(path)
+    }
+
+    fn trash(&self, path: &Path) -> Result<(), Error> {
+        trash::delete(path).map_err(|e| Error::other(format!("can't move {} to the trash: {}", path.to_string_lossy(), e)))
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn unsupported(e: Error) -> Error {
+    match e.raw_os_error() {
+        Some(libc::EOPNOTSUPP) | Some(libc::EXDEV) | Some(libc::EINVAL) | Some(libc::ENOTTY) => {
+            Error::new(ErrorKind::Unsupported, "the filesystem doesn't support reflinks")
+        },
+        _ => e,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn clone_file(source: &Path, destination_path: &Path) -> Result<(), Error> {
+    use std::os::unix::io::AsRawFd;
+    //_IOW(0x94, 9, int) from linux/fs.h
+    const FICLONE: libc::c_ulong = 0x40049409;
+    let source = fs::File::open(source)?;
+    let destination = fs::OpenOptions::new().write(true).create_new(true).open(destination_path)?;
+    let result = unsafe { libc::ioctl(destination.as_raw_fd(), FICLONE as _, source.as_raw_fd()) };
+    if result == -1 {
+        let e = unsupported(Error::last_os_error());
+        //Created above by this call, nobody else's
+        let _ = fs::remove_file(destination_path);
+        return Err(e);
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn clone_file(source: &Path, destination: &Path) -> Result<(), Error> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    extern "C" {
+        fn clonefile(src: *const libc::c_char, dst: *const libc::c_char, flags: u32) -> libc::c_int;
+    }
+    let source = CString::new(source.as_os_str().as_bytes())?;
+    let destination = CString::new(destination.as_os_str().as_bytes())?;
+    let result = unsafe { clonefile(source.as_ptr(), destination.as_ptr(), 0) };
+    if result == -1 {
+        return Err(unsupported(Error::last_os_error()));
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn clone_file(_source: &Path, _destination: &Path) -> Result<(), Error> {
+    Err(Error::new(ErrorKind::Unsupported, "reflinks are only supported on Linux and macOS"))
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    File(u64),
+    Symlink(PathBuf),
+}
+
+#[derive(Debug, Clone)]
+struct Inode {
+    content: Vec<u8>,
+    readonly: bool,
+    modified: SystemTime,
+}
+
+#[derive(Debug, Default)]
+struct Tree {
+    nodes: HashMap<PathBuf, Node>,
+    inodes: HashMap<u64, Inode>,
+    trashed: Vec<PathBuf>,
+}
+
+/*
+ * Tree of files held in memory, built with add_file, add_hard_link and add_symlink.
+ * Paths are taken as given, without directories: they exist once added. Files are
+ * modified at the epoch unless set otherwise, which keeps them clear of the recent rule
+ * of action::Risks. Clones get an inode of their own, trashed files are only listed.
+ */
+#[derive(Debug, Default)]
+pub struct Memory {
+    tree: RefCell<Tree>,
+}
+
+fn not_found(path: &Path) -> Error {
+    Error::new(ErrorKind::NotFound, format!("{} doesn't exist", path.to_string_lossy()))
+}
+
+impl Memory {
+    pub fn new() -> Memory {
+        Memory::default()
+    }
+
+    pub fn add_file(&self, path: &Path, content: &[u8]) {
+        let mut tree = self.tree.borrow_mut();
+        let number = tree.inodes.len() as u64 + 1;
+        tree.inodes.insert(number, Inode { content: content.to_vec(), readonly: false, modified: SystemTime::UNIX_EPOCH });
+        tree.nodes.insert(path.to_path_buf(), Node::File(number));
+    }
+
+    pub fn add_hard_link(&self, path: &Path, existing: &Path) -> Result<(), Error> {
+        let mut tree = self.tree.borrow_mut();
+        let node = tree.nodes.get(existing).cloned().ok_or_else(|| not_found(existing))?;
+        tree.nodes.insert(path.to_path_buf(), node);
+        Ok(())
+    }
+
+    pub fn add_symlink(&self, path: &Path, target: &Path) {
+        self.tree.borrow_mut().nodes.insert(path.to_path_buf(), Node::Symlink(target.to_path_buf()));
+    }
+
+    pub fn set_readonly(&self, path: &Path, readonly: bool) -> Result<(), Error> {
+        self.with_inode(path, |inode| inode.readonly = readonly)
+    }
+
+    pub fn set_modified(&self, path: &Path, modified: SystemTime) -> Result<(), Error> {
+        self.with_inode(path, |inode| inode.modified = modified)
+    }
+
+    //Content of the file at path, following symbolic links
+    pub fn content(&self, path: &Path) -> Option<Vec<u8>> {
+        let tree = self.tree.borrow();
+        resolve(&tree, path).ok().and_then(|(_, n)| tree.inodes.get(&n)).map(|i| i.content.clone())
+    }
+
+    //Paths moved to the trash, in order
+    pub fn trashed(&self) -> Vec<PathBuf> {
+        self.tree.borrow().trashed.clone()
+    }
+
+    fn with_inode<F: FnOnce(&mut Inode)>(&self, path: &Path, change: F) -> Result<(), Error> {
+        let mut tree = self.tree.borrow_mut();
+        let (_, number) = resolve(&tree, path)?;
+        tree.inodes.get_mut(&number).map(change).ok_or_else(|| not_found(path))
+    }
+}
+
+//Path and inode number of the file at path, through the symbolic links
+fn resolve(tree: &Tree, path: &Path) -> Result<(PathBuf, u64), Error> {
+    let mut current = path.to_path_buf();
+    //A loop of links has to end somewhere, as it does on disk
+    for _ in 0..40 {
+        match tree.nodes.get(&current) {
+            Some(Node::File(number)) => return Ok((current, *number)),
+            Some(Node::Symlink(target)) => current = target.clone(),
+            None => return Err(not_found(path)),
+        }
+    }
+    Err(Error::other(format!("too many levels of symbolic links at {}", path.to_string_lossy())))
+}
+
+fn inode_info(number: u64, inode: &Inode) -> FileInfo {
+    FileInfo {
+        len: inode.content.len() as u64,
+        is_file: true,
+        inode: Some((0, number)),
+        readonly: inode.readonly,
+        modified: Some(inode.modified),
+    }
+}
+
+impl FileSystem for Memory {
+    fn symlink_metadata(&self, path: &Path) -> Result<FileInfo, Error> {
+        let tree = self.tree.borrow();
+        match tree.nodes.get(path) {
+            Some(Node::Symlink(target)) => Ok(FileInfo {
+                len: target.as_os_str().len() as u64,
+                is_file: false,
+                inode: None,
+                readonly: false,
+                modified: None,
+            }),
+            Some(Node::File(number)) => Ok(inode_info(*number, &tree.inodes[number])),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileInfo, Error> {
+        let tree = self.tree.borrow();
+        let (_, number) = resolve(&tree, path)?;
+        Ok(inode_info(number, &tree.inodes[&number]))
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf, Error> {
+        resolve(&self.tree.borrow(), path).map(|(canonical, _)| canonical)
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn Read>, Error> {
+        let content = self.content(path).ok_or_else(|| not_found(path))?;
+        Ok(Box::new(Cursor::new(content)))
+    }
+
+    fn hard_link(&self, existing: &Path, link: &Path) -> Result<(), Error> {
+        if self.tree.borrow().nodes.contains_key(link) {
+            return Err(Error::new(ErrorKind::AlreadyExists, format!("{} already exists", link.to_string_lossy())));
+        }
+        self.add_hard_link(link, existing)
+    }
+
+    fn clone_file(&self, source: &Path, destination: &Path) -> Result<(), Error> {
+        let content = self.content(source).ok_or_else(|| not_found(source))?;
+        if self.tree.borrow().nodes.contains_key(destination) {
+            return Err(Error::new(ErrorKind::AlreadyExists, format!("{} already exists", destination.to_string_lossy())));
+        }
+        self.add_file(destination, &content);
+        Ok(())
+    }
+
+    fn copy_permissions(&self, from: &Path, to: &Path) -> Result<(), Error> {
+        let readonly = self.metadata(from)?.readonly;
+        self.set_readonly(to, readonly)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), Error> {
+        let mut tree = self.tree.borrow_mut();
+        let node = tree.nodes.remove(from).ok_or_else(|| not_found(from))?;
+        tree.nodes.insert(to.to_path_buf(), node);
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<(), Error> {
+        self.tree.borrow_mut().nodes.remove(path).map(|_| ()).ok_or_else(|| not_found(path))
+    }
+
+    fn trash(&self, path: &Path) -> Result<(), Error> {
+        self.remove_file(path)?;
+        self.tree.borrow_mut().trashed.push(path.to_path_buf());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_are_followed() {
+        let memory = Memory::new();
+        memory.add_file(Path::new("a"), b"abc");
+        memory.add_symlink(Path::new("b"), Path::new("a"));
+        memory.add_symlink(Path::new("c"), Path::new("b"));
+        assert_eq!(memory.canonicalize(Path::new("c")).unwrap(), PathBuf::from("a"));
+        let mut content = Vec::new();
+        memory.open(Path::new("c")).unwrap().read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"abc");
+    }
+
+    #[test]
+    fn link_loops_end() {
+        let memory = Memory::new();
+        memory.add_symlink(Path::new("a"), Path::new("b"));
+        memory.add_symlink(Path::new("b"), Path::new("a"));
+        assert!(memory.canonicalize(Path::new("a")).is_err());
+        assert!(memory.metadata(Path::new("a")).is_err());
+    }
+
+    #[test]
+    fn hard_links_share_the_inode() {
+        let memory = Memory::new();
+        memory.add_file(Path::new("a"), b"abc");
+        memory.hard_link(Path::new("a"), Path::new("b")).unwrap();
+        assert!(memory.metadata(Path::new("a")).unwrap().same_inode(&memory.metadata(Path::new("b")).unwrap()));
+        assert_eq!(memory.hard_link(Path::new("a"), Path::new("b")).unwrap_err().kind(), ErrorKind::AlreadyExists);
+        assert_eq!(memory.hard_link(Path::new("c"), Path::new("d")).unwrap_err().kind(), ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,752 @@
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash as _, Hasher};
+use std::io::{BufReader, Error, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use walkdir::DirEntry;
+use sha2::Sha256;
+use unicode_normalization::UnicodeNormalization;
+use crate::deadline::Deadline;
+use crate::fuzzy;
+use crate::group::DuplicateGroup;
+use crate::hash::{self, Hash};
+use crate::manifest::Manifest;
+use crate::size::format_size;
+use crate::throttle::Throttle;
+
+/*
+ * Constants for hash skipping
+ * TODO: provide the values through arguments
+ */
+pub const SMALL_FILE_SIZE: u64 = 1024 * 1024 * 8; // 1 Mb
+pub const BIG_FILE_SIZE: u64 = 1024 * SMALL_FILE_SIZE; // 1 Gb
+
+//Wait before the first retry of a read, doubled on each of the next ones
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+//Bytes left to hash after bucketing from which a hint suggests the cheaper comparisons
+pub const HINT_SIZE: u64 = 1024 * 1024 * 1024 * 16; // 16 Gb
+
+//Most trailing zeros left out of the content by h --ignore-trailing-zeros, above a block size of any tool
+pub const PADDING_LIMIT: u64 = 1024 * 1024; // 1 Mb
+
+//Settings shared by the content comparisons
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HashOptions {
+    //skip files bigger than BIG_FILE_SIZE
+    pub skip_big: bool,
+    //skip files smaller than SMALL_FILE_SIZE
+    pub skip_small: bool,
+    //report the files without duplicate instead of the duplicates
+    pub unique: bool,
+    //leave every extension out of the name part of the key
+    pub ignore_extension: bool,
+    //hash through read errors, the files concerned only group with each other (h)
+    pub tolerant_read: bool,
+    //compare the contents of the members of each group byte for byte and split it on differences
+    pub verify: bool,
+    //tell on stderr about cheaper comparisons before a long hashing
+    pub hints: bool,
+    //times a file is read again after a transient error (see retrying)
+    pub retries: u32,
+}
+
+impl HashOptions {
+    pub(crate) fn skips(&self, size: u64) -> bool {
+        (self.skip_big && size > BIG_FILE_SIZE) || (self.skip_small && size < SMALL_FILE_SIZE)
+    }
+
+    pub(crate) fn key_name(&self, entry: &DirEntry) -> String {
+        let name = key_name(entry, false);
+        if self.ignore_extension {
+            String::from(strip_extensions(&name))
+        } else {
+            name
+        }
+    }
+}
+
+/*
+ * Split of a file name into its stem and all of its extensions: `song.flac.bak` gives
+ * `song` and `flac.bak`. A leading dot is part of the name, `.bashrc` has no extension.
+ * Every feature looking at extensions goes through here so they all agree on them.
+ */
+pub fn split_extension(name: &str) -> (&str, Option<&str>) {
+    match name.char_indices().skip(1).find(|c| c.1 == '.') {
+        Some((i, _)) => (&name[..i], Some(&name[i + 1..])),
+        None => (name, None),
+    }
+}
+
+//Name without any of its extensions, so `song.flac` and `song.flac.bak` both become `song`
+pub fn strip_extensions(name: &str) -> &str {
+    split_extension(name).0
+}
+
+//Extensions of the name in lowercase, `.JPG` and `.jpg` being the same extension
+pub fn extension(name: &str) -> Option<String> {
+    split_extension(name).1.map(str::to_lowercase)
+}
+
+/*
+ * A directory of the walk. The root, walked into even when it is a symbolic link, keeps
+ * the file type of the link: a link to a directory given as root is a directory too.
+ */
+pub fn is_directory(entry: &DirEntry) -> bool {
+    entry.file_type().is_dir() || (entry.depth() == 0 && entry.path_is_symlink() && entry.path().is_dir())
+}
+
+//Name used in the grouping keys, the paths reported are left untouched
+pub fn key_name(entry: &DirEntry, normalize: bool) -> String {
+    let name = entry.file_name().to_string_lossy();
+    if normalize {
+        name.nfc().collect()
+    } else {
+        String::from(name)
+    }
+}
+
+fn paths(files: Vec<DirEntry>) -> Vec<std::path::PathBuf> {
+    files.into_iter().map(DirEntry::into_path).collect()
+}
+
+//Set by --verbose, the files passed over as vanished and where compared files differ are then told on stderr
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+pub fn verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+//A file found by the walk is gone, told under --verbose
+pub fn vanished(path: &Path) {
+    if verbose() {
+        eprintln!("vanished: {}", path.to_string_lossy());
+    }
+}
+
+/*
+ * Files can vanish between the walk and their reading on a busy tree: NotFound becomes
+ * None so the file at path is passed over instead of failing the whole run.
+ */
+pub fn unless_vanished<T, E: Into<Error>>(path: &Path, result: Result<T, E>) -> Result<Option<T>, Error> {
+    match result.map_err(Into::into) {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            vanished(path);
+            Ok(None)
+        },
+        Err(e) => Err(e),
+    }
+}
+
+//Errors of flaky network mounts that may not happen again, never NotFound nor PermissionDenied
+fn transient(e: &Error) -> bool {
+    matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted)
+}
+
+/*
+ * Read a file again, up to `retries` times with a growing wait, while it fails with a
+ * transient error. The read starts over from the beginning each time. The files that
+ * needed it are counted in the throttle.
+ */
+pub(crate) fn retrying<T, R>(retries: u32, throttle: &mut Throttle, mut read: R) -> Result<T, Error>
+where R: FnMut(&mut Throttle) -> Result<T, Error>,
+{
+    let mut delay = RETRY_DELAY;
+    let mut attempt = 0;
+    loop {
+        match read(throttle) {
+            Err(e) if attempt < retries && transient(&e) => {
+                if attempt == 0 {
+                    throttle.count_retried();
+                }
+                attempt += 1;
+                thread::sleep(delay);
+                delay *= 2;
+            },
+            result => return result,
+        }
+    }
+}
+
+//Collect every group streamed by one of the for_each_* functions
+pub(crate) fn collect<F>(run: F) -> Result<Vec<DuplicateGroup>, Error>
+where F: FnOnce(&mut dyn FnMut(DuplicateGroup) -> Result<(), Error>) -> Result<(), Error>,
+{
+    let mut groups = Vec::new();
+    run(&mut |group| {
+        groups.push(group);
+        Ok(())
+    })?;
+    Ok(groups)
+}
+
+pub fn find_by_names<I>(iter: I, normalize: bool) -> Result<Vec<DuplicateGroup>, Error>
+where I: IntoIterator<Item= DirEntry>,
+{
+    collect(|emit| for_each_by_names(iter, normalize, emit))
+}
+
+pub fn for_each_by_names<I, F>(iter: I, normalize: bool, mut emit: F) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    let mut filenames : HashMap<String, Vec<DirEntry>> = HashMap::new();
+    for entry in iter.into_iter() {
+        let f_name = key_name(&entry, normalize);
+        let counter = filenames.entry(f_name).or_default();
+        counter.push(entry);
+    }
+    for (name, files) in filenames.into_iter().filter(|e| e.1.len() != 1) {
+        emit(DuplicateGroup::new(name, None, None, paths(files)))?;
+    }
+    Ok(())
+}
+
+pub fn find_by_repeated_names<I, J>(first: I, iter: J, normalize: bool) -> Result<Vec<DuplicateGroup>, Error>
+where I: IntoIterator<Item= DirEntry>,
+      J: IntoIterator<Item= DirEntry>,
+{
+    collect(|emit| for_each_by_repeated_names(first, iter, normalize, emit))
+}
+
+/*
+ * Same groups as for_each_by_names, from two walks of the same files. The first one
+ * only keeps a 64 bits hash of each name along whether it was seen twice, the second
+ * one then keeps the entries of the repeated hashes alone: memory follows the number of
+ * names rather than their length, and the entries of the duplicates only.
+ * A hash shared by different names just keeps a few more entries, grouping stays on names.
+ */
+pub fn for_each_by_repeated_names<I, J, F>(first: I, iter: J, normalize: bool, emit: F) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      J: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    let name_hash = |entry: &DirEntry| {
+        let mut hasher = DefaultHasher::new();
+        key_name(entry, normalize).hash(&mut hasher);
+        hasher.finish()
+    };
+    let mut repeated : HashMap<u64, bool> = HashMap::new();
+    for entry in first.into_iter() {
+        repeated.entry(name_hash(&entry)).and_modify(|r| *r = true).or_insert(false);
+    }
+    repeated.retain(|_, r| *r);
+    for_each_by_names(iter.into_iter().filter(|e| repeated.contains_key(&name_hash(e))), normalize, emit)
+}
+
+pub fn find_by_names_sizes<I>(iter: I, normalize: bool) -> Result<Vec<DuplicateGroup>, Error>
+where I: IntoIterator<Item= DirEntry>,
+{
+    collect(|emit| for_each_by_names_sizes(iter, normalize, emit))
+}
+
+pub fn for_each_by_names_sizes<I, F>(iter: I, normalize: bool, mut emit: F) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    let mut filenames : HashMap<(String,u64), Vec<DirEntry>> = HashMap::new();
+    for entry in iter.into_iter() {
+        let f_name = key_name(&entry, normalize);
+        let f_size = match unless_vanished(entry.path(), entry.metadata())? {
+            Some(metadata) => metadata.len(),
+            None => continue,
+        };
+        let counter = filenames.entry((f_name,f_size)).or_default();
+        counter.push(entry);
+    }
+    for ((name, size), files) in filenames.into_iter().filter(|e| e.1.len() != 1) {
+        emit(DuplicateGroup::new(name, Some(size), None, paths(files)))?;
+    }
+    Ok(())
+}
+
+pub fn find_confirmed<I>(iter: I, normalize: bool, throttle: &mut Throttle, deadline: &Deadline) -> Result<Vec<DuplicateGroup>, Error>
+where I: IntoIterator<Item= DirEntry>,
+{
+    collect(|emit| for_each_confirmed(iter, normalize, throttle, deadline, emit))
+}
+
+/*
+ * The name and size groups, each split by the hashes of its members: only the files
+ * sharing their content with another one stay, as with h, while the files alone with
+ * their name and size are never read.
+ */
+pub fn for_each_confirmed<I, F>(iter: I, normalize: bool, throttle: &mut Throttle, deadline: &Deadline, mut emit: F) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    for_each_by_names_sizes(iter, normalize, |group| {
+        let mut hashes : HashMap<Hash, Vec<PathBuf>> = HashMap::new();
+        for file in group.files.into_iter() {
+            if deadline.expired() {
+                break
+            }
+            if let Some(f_hash) = unless_vanished(&file, hash::hash_file(&file, throttle))? {
+                hashes.entry(f_hash).or_default().push(file);
+            }
+        }
+        for (f_hash, files) in hashes.into_iter().filter(|e| e.1.len() > 1) {
+            emit(DuplicateGroup::new(group.name.clone(), group.size, Some(format!("{:x}", f_hash)), files))?;
+        }
+        Ok(())
+    })
+}
+
+pub fn find_by_hashes<I>(iter: I, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline) -> Result<Vec<DuplicateGroup>, Error>
+where I: IntoIterator<Item= DirEntry>,
+{
+    collect(|emit| for_each_by_hashes(iter, options, throttle, deadline, emit))
+}
+
+pub fn for_each_by_hashes<I, F>(iter: I, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline, emit: F) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    by_hashes(iter, options, None, throttle, deadline, emit)
+}
+
+/*
+ * Same as for_each_by_hashes, also writing the hash of every file to the manifest.
+ * The files alone in their name and size bucket are then hashed too, suspect hashes
+ * are left out of it.
+ */
+pub fn for_each_by_hashes_listed<I, F>(iter: I, options: HashOptions, manifest: &mut Manifest, throttle: &mut Throttle, deadline: &Deadline, emit: F) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    by_hashes(iter, options, Some(manifest), throttle, deadline, emit)
+}
+
+fn by_hashes<I, F>(iter: I, options: HashOptions, manifest: Option<&mut Manifest>, throttle: &mut Throttle, deadline: &Deadline, emit: F) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    if !options.tolerant_read {
+        return for_each_by_digest(iter, options, manifest, throttle, deadline, emit, |entry, throttle| Ok((hash::hash_file(entry.path(), throttle)?, false)));
+    }
+    for_each_by_digest(iter, options, manifest, throttle, deadline, emit, |entry, throttle| {
+        let (f_hash, bad) = hash::hash_file_tolerant(entry.path(), throttle)?;
+        for offset in bad.iter() {
+            eprintln!("warning: read error in {} at offset {}, hashed as zeros", entry.path().to_string_lossy(), offset);
+        }
+        Ok((f_hash, !bad.is_empty()))
+    })
+}
+
+pub fn find_by_ends<I>(iter: I, bytes: u64, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline) -> Result<Vec<DuplicateGroup>, Error>
+where I: IntoIterator<Item= DirEntry>,
+{
+    collect(|emit| for_each_by_ends(iter, bytes, options, throttle, deadline, emit))
+}
+
+//Same as for_each_by_hashes, only the first and last `bytes` bytes of each file are hashed
+pub fn for_each_by_ends<I, F>(iter: I, bytes: u64, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline, emit: F) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    for_each_by_digest(iter, options, None, throttle, deadline, emit, |entry, throttle| Ok((hash::hash_ends(entry.path(), bytes, throttle)?, false)))
+}
+
+pub fn find_by_payload<I>(iter: I, header: u64, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline) -> Result<Vec<DuplicateGroup>, Error>
+where I: IntoIterator<Item= DirEntry>,
+{
+    collect(|emit| for_each_by_payload(iter, header, options, throttle, deadline, emit))
+}
+
+/*
+ * Groups of files with the same content past their first `header` bytes, whatever these
+ * are: weaker than a whole hash, the files of a group aren't identical. The size
+ * is still part of the key, as with a fixed length header it is the payload's too.
+ * Files with nothing past the header would all have the same hash and are left out.
+ */
+pub fn for_each_by_payload<I, F>(iter: I, header: u64, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline, emit: F) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    let iter = iter.into_iter().filter(|e| e.metadata().is_ok_and(|m| m.len() > header));
+    for_each_by_digest(iter, options, None, throttle, deadline, emit, |entry, throttle| Ok((hash::hash_from(entry.path(), header, throttle)?, false)))
+}
+
+pub fn find_by_range<I>(iter: I, start: u64, len: u64, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline) -> Result<Vec<DuplicateGroup>, Error>
+where I: IntoIterator<Item= DirEntry>,
+{
+    collect(|emit| for_each_by_range(iter, start, len, options, throttle, deadline, emit))
+}
+
+/*
+ * Groups of files with the same `len` bytes from `start` on, for formats keeping what
+ * identifies their content at a known place. As with for_each_by_payload the files of a
+ * group aren't identical and the size is still part of the key.
+ * Files ending before the end of the range are left out.
+ */
+pub fn for_each_by_range<I, F>(iter: I, start: u64, len: u64, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline, emit: F) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    let end = start.saturating_add(len);
+    let iter = iter.into_iter().filter(|e| e.metadata().is_ok_and(|m| m.len() >= end));
+    for_each_by_digest(iter, options, None, throttle, deadline, emit, |entry, throttle| Ok((hash::hash_range(entry.path(), start, len, throttle)?, false)))
+}
+
+pub fn find_matching<I>(iter: I, wanted: &HashSet<String>, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline) -> Result<Vec<DuplicateGroup>, Error>
+where I: IntoIterator<Item= DirEntry>,
+{
+    collect(|emit| for_each_matching(iter, wanted, options, throttle, deadline, emit))
+}
+
+/*
+ * Search of known contents: every file is hashed, whatever its name and size, and the
+ * ones whose hash is `wanted` are reported in one group per hash, a lone file included.
+ * Groups only come once the whole tree is hashed, after a timeout the files hashed so
+ * far are reported.
+ */
+pub fn for_each_matching<I, F>(iter: I, wanted: &HashSet<String>, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline, mut emit: F) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    let mut found : HashMap<String, (u64, Vec<DirEntry>)> = HashMap::new();
+    for entry in iter.into_iter() {
+        if deadline.expired() {
+            break
+        }
+        let f_size = match unless_vanished(entry.path(), entry.metadata())? {
+            Some(metadata) => metadata.len(),
+            None => continue,
+        };
+        if options.skips(f_size) {
+            continue
+        }
+        let f_hash = match unless_vanished(entry.path(), retrying(options.retries, throttle, |throttle| hash::hash_file(entry.path(), throttle)))? {
+            Some(f_hash) => format!("{:x}", f_hash),
+            None => continue,
+        };
+        if wanted.contains(&f_hash) {
+            found.entry(f_hash).or_insert((f_size, Vec::new())).1.push(entry);
+        }
+    }
+    for (f_hash, (size, files)) in found.into_iter() {
+        let name = files[0].file_name().to_string_lossy().into_owned();
+        emit(DuplicateGroup::new(name, Some(size), Some(f_hash), paths(files)))?;
+    }
+    Ok(())
+}
+
+pub fn find_by_unpadded<I>(iter: I, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline) -> Result<Vec<DuplicateGroup>, Error>
+where I: IntoIterator<Item= DirEntry>,
+{
+    collect(|emit| for_each_by_unpadded(iter, options, throttle, deadline, emit))
+}
+
+/*
+ * Groups of files with the same content once the zeros they end with are left out, up to
+ * PADDING_LIMIT of them, for copies padded to a block size by a transfer or an image tool.
+ * Sizes legitimately differ and aren't part of the key, only names are.
+ * A heuristic: files that really end in different amounts of zeros group as well.
+ */
+pub fn for_each_by_unpadded<I, F>(iter: I, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline, mut emit: F) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    let mut filenames : HashMap<String, Vec<DirEntry>> = HashMap::new();
+    for entry in iter.into_iter() {
+        let f_size = match unless_vanished(entry.path(), entry.metadata())? {
+            Some(metadata) => metadata.len(),
+            None => continue,
+        };
+        if options.skips(f_size) {
+            continue
+        }
+        filenames.entry(options.key_name(&entry)).or_default().push(entry);
+    }
+    for (name, files) in filenames.into_iter() {
+        if files.len() == 1 && !options.unique {
+            continue
+        }
+        let mut hashes : HashMap<Hash, Vec<DirEntry>> = HashMap::new();
+        let mut complete = true;
+        for entry in files.into_iter() {
+            if deadline.expired() {
+                complete = false;
+                break
+            }
+            if let Some(f_hash) = unless_vanished(entry.path(), retrying(options.retries, throttle, |throttle| hash::hash_unpadded(entry.path(), PADDING_LIMIT, throttle)))? {
+                hashes.entry(f_hash).or_default().push(entry);
+            }
+        }
+        if complete || !options.unique {
+            for (f_hash, files) in hashes.into_iter().filter(|e| (e.1.len() == 1) == options.unique) {
+                emit(DuplicateGroup::new(name.clone(), None, Some(format!("{:x}", f_hash)), paths(files)))?;
+            }
+        }
+        if !complete {
+            break
+        }
+    }
+    Ok(())
+}
+
+/*
+ * Lone name and size buckets are never hashed, so what makes h slow is many big files
+ * sharing a bucket. Before reading them the user is told once that `s` already lists
+ * these candidates and that `--ends` sorts most of them out for a fraction of the reads.
+ */
+fn hint(buckets: &HashMap<(String,u64), Vec<DirEntry>>, all: bool) {
+    let (files, bytes) = buckets.iter()
+        .filter(|b| all || b.1.len() > 1)
+        .fold((0, 0), |(files, bytes), ((_, size), entries)| (files + entries.len(), bytes + size * entries.len() as u64));
+    if bytes >= HINT_SIZE {
+        eprintln!("hint: {} to hash in {} files; `s` lists the candidates without reading them, `--ends 1M` tells most apart reading a few MB of each (--no-hints to silence)", format_size(bytes), files);
+    }
+}
+
+/*
+ * Files are first bucketed by name and size, then each bucket is hashed on its own:
+ * its groups are final as soon as the bucket is done and are emitted right away.
+ * The digest tells along the hash whether it is suspect, suspect and clean hashes never group.
+ * With `verify`, the members of each group are then compared byte for byte.
+ */
+fn for_each_by_digest<I, F, D>(iter: I, options: HashOptions, mut manifest: Option<&mut Manifest>, throttle: &mut Throttle, deadline: &Deadline, mut emit: F, digest: D) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+      D: Fn(&DirEntry, &mut Throttle) -> Result<(Hash, bool), Error>,
+{
+    let mut filenames : HashMap<(String,u64), Vec<DirEntry>> = HashMap::new();
+    for entry in iter.into_iter() {
+        let f_name = options.key_name(&entry);
+        let f_size = match unless_vanished(entry.path(), entry.metadata())? {
+            Some(metadata) => metadata.len(),
+            None => continue,
+        };
+        if options.skips(f_size) {
+            continue
+        }
+        let counter = filenames.entry((f_name,f_size)).or_default();
+        counter.push(entry);
+    }
+    if options.hints {
+        hint(&filenames, options.unique || manifest.is_some());
+    }
+    for ((name, size), files) in filenames.into_iter() {
+        //Alone in its bucket a file can't have a duplicate, it only needs hashing to be reported unique or listed
+        if files.len() == 1 && !options.unique && manifest.is_none() {
+            continue
+        }
+        let mut hashes : HashMap<(Hash, bool), Vec<DirEntry>> = HashMap::new();
+        let mut complete = true;
+        for entry in files.into_iter() {
+            if deadline.expired() {
+                complete = false;
+                break
+            }
+            let key = match unless_vanished(entry.path(), retrying(options.retries, throttle, |throttle| digest(&entry, throttle)))? {
+                Some(key) => key,
+                None => continue,
+            };
+            if let (Some(manifest), false) = (manifest.as_mut(), key.1) {
+                manifest.record(entry.path(), &format!("{:x}", key.0))?;
+            }
+            hashes.entry(key).or_default().push(entry);
+        }
+        //An interrupted bucket may still hold unhashed copies, only its duplicates are certain
+        if complete || !options.unique {
+            //Unique files are exactly the groups left out of the duplicates listing
+            for ((f_hash, suspect), files) in hashes.into_iter().filter(|e| (e.1.len() == 1) == options.unique) {
+                let classes = if options.verify && files.len() > 1 { fuzzy::identical(files, throttle)? } else { vec![files] };
+                let split = classes.len() > 1;
+                for files in classes.into_iter().filter(|c| (c.len() == 1) == options.unique) {
+                    let mut group = DuplicateGroup::new(name.clone(), Some(size), Some(format!("{:x}", f_hash)), paths(files));
+                    group.suspect = suspect;
+                    group.split = split;
+                    emit(group)?;
+                }
+            }
+        }
+        if !complete {
+            break
+        }
+    }
+    Ok(())
+}
+
+pub fn find_by_fuzzy_bytes<I>(iter: I, max_diff: u64, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline) -> Result<Vec<DuplicateGroup>, Error>
+where I: IntoIterator<Item= DirEntry>,
+{
+    collect(|emit| for_each_by_fuzzy_bytes(iter, max_diff, options, throttle, deadline, emit))
+}
+
+pub fn for_each_by_fuzzy_bytes<I, F>(iter: I, max_diff: u64, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline, mut emit: F) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    let mut filenames : HashMap<(String,u64), Vec<DirEntry>> = HashMap::new();
+    for entry in iter.into_iter() {
+        let f_name = options.key_name(&entry);
+        let f_size = match unless_vanished(entry.path(), entry.metadata())? {
+            Some(metadata) => metadata.len(),
+            None => continue,
+        };
+        if options.skips(f_size) {
+            continue
+        }
+        let counter = filenames.entry((f_name,f_size)).or_default();
+        counter.push(entry);
+    }
+    for ((name, size), files) in filenames.into_iter() {
+        if deadline.expired() {
+            break
+        }
+        let clusters = fuzzy::clusters(files, max_diff, throttle)?;
+        let split = clusters.len() > 1;
+        for cluster in clusters.into_iter().filter(|c| (c.len() == 1) == options.unique) {
+            let mut group = DuplicateGroup::new(name.clone(), Some(size), None, paths(cluster));
+            group.split = split;
+            emit(group)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn find_truncated<I>(iter: I, normalize: bool, throttle: &mut Throttle, deadline: &Deadline) -> Result<Vec<DuplicateGroup>, Error>
+where I: IntoIterator<Item= DirEntry>,
+{
+    collect(|emit| for_each_truncated(iter, normalize, throttle, deadline, emit))
+}
+
+/*
+ * Within each name group, look for files that are an exact prefix of a longer one by
+ * hashing the longer file only up to the length of the shorter one.
+ * Each finding is reported as a group of the suspect file and the most complete copy.
+ */
+pub fn for_each_truncated<I, F>(iter: I, normalize: bool, throttle: &mut Throttle, deadline: &Deadline, mut emit: F) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    let mut filenames : HashMap<String, Vec<(u64, DirEntry)>> = HashMap::new();
+    for entry in iter.into_iter() {
+        let f_name = key_name(&entry, normalize);
+        let f_size = match unless_vanished(entry.path(), entry.metadata())? {
+            Some(metadata) => metadata.len(),
+            None => continue,
+        };
+        filenames.entry(f_name).or_default().push((f_size, entry));
+    }
+    for (name, mut files) in filenames.into_iter().filter(|e| e.1.len() != 1) {
+        if deadline.expired() {
+            break
+        }
+        //Longest first so the suspect is matched against the most complete copy
+        files.sort_by_key(|f| std::cmp::Reverse(f.0));
+        for (i, (size, short)) in files.iter().enumerate() {
+            let prefix = match unless_vanished(short.path(), hash::hash_file(short.path(), throttle))? {
+                Some(prefix) => prefix,
+                None => continue,
+            };
+            for (long_size, long) in files[..i].iter().filter(|f| f.0 > *size) {
+                let mut reader = match unless_vanished(long.path(), fs::File::open(long.path()))? {
+                    Some(file) => BufReader::new(file).take(*size),
+                    None => continue,
+                };
+                if hash::process::<Sha256,_>(&mut reader, throttle)? == prefix {
+                    emit(DuplicateGroup {
+                        name: name.clone(),
+                        size: Some(*long_size),
+                        hash: Some(format!("{:x}", prefix)),
+                        files: vec![short.path().to_path_buf(), long.path().to_path_buf()],
+                        truncated: Some(short.path().to_path_buf()),
+                        overlap: None,
+                        ownership: None,
+                        sources: None,
+                        suspect: false,
+                        compressed: Vec::new(),
+                        survivor: None,
+                        directory_files: None,
+                        split: false,
+                        id: None,
+                    })?;
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extensions_are_lowercase() {
+        assert_eq!(extension("photo.JPG").as_deref(), Some("jpg"));
+        assert_eq!(extension("photo.Jpg").as_deref(), Some("jpg"));
+        assert_eq!(extension("photo.jpg").as_deref(), Some("jpg"));
+    }
+
+    #[test]
+    fn compound_extensions() {
+        assert_eq!(split_extension("backup.tar.gz"), ("backup", Some("tar.gz")));
+        assert_eq!(extension("backup.TAR.Gz").as_deref(), Some("tar.gz"));
+        assert_eq!(strip_extensions("song.flac.bak"), "song");
+    }
+
+    #[test]
+    fn names_without_extension() {
+        assert_eq!(split_extension(".bashrc"), (".bashrc", None));
+        assert_eq!(split_extension(".config.json"), (".config", Some("json")));
+        assert_eq!(extension("README"), None);
+    }
+
+    #[test]
+    fn vanished_files_are_passed_over() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["x", "y", "z"] {
+            fs::create_dir(dir.path().join(name)).unwrap();
+            fs::write(dir.path().join(name).join("a"), "same").unwrap();
+        }
+        let mut walk = walkdir::WalkDir::new(dir.path()).into_iter().filter_map(Result::ok).filter(|e| e.file_type().is_file());
+        //z/a is deleted once walked, before the hashing reads it
+        let gone = dir.path().join("z/a");
+        let iter = std::iter::from_fn(|| {
+            let next = walk.next();
+            if next.is_none() && gone.exists() {
+                fs::remove_file(&gone).unwrap();
+            }
+            next
+        });
+        let groups = find_confirmed(iter, false, &mut Throttle::new(None), &Deadline::default()).unwrap();
+        assert_eq!(groups.len(), 1);
+        let mut files = groups[0].files.clone();
+        files.sort();
+        assert_eq!(files, vec![dir.path().join("x/a"), dir.path().join("y/a")]);
+    }
+
+    #[test]
+    fn clusters_of_one_key_have_their_own_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in [("w", "aaaa"), ("x", "aaaa"), ("y", "zzzz"), ("z", "zzzz")] {
+            fs::create_dir(dir.path().join(name)).unwrap();
+            fs::write(dir.path().join(name).join("a"), content).unwrap();
+        }
+        let walk = || walkdir::WalkDir::new(dir.path()).into_iter().filter_map(Result::ok).filter(|e| e.file_type().is_file());
+        let groups = find_by_fuzzy_bytes(walk(), 0, HashOptions::default(), &mut Throttle::new(None), &Deadline::default()).unwrap();
+        assert_eq!(groups.len(), 2);
+        assert!(groups.iter().all(|g| g.split));
+        assert_ne!(groups[0].stable_id(), groups[1].stable_id());
+        //Nor do the files left alone under -u
+        fs::remove_file(dir.path().join("x/a")).unwrap();
+        fs::remove_file(dir.path().join("z/a")).unwrap();
+        let unique = HashOptions { unique: true, ..HashOptions::default() };
+        let groups = find_by_fuzzy_bytes(walk(), 0, unique, &mut Throttle::new(None), &Deadline::default()).unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_ne!(groups[0].stable_id(), groups[1].stable_id());
+    }
+}
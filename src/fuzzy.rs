@@ -0,0 +1,264 @@
+use std::fs;
+use std::io::{BufReader, Error, ErrorKind, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use memmap2::Mmap;
+use walkdir::DirEntry;
+use crate::find;
+use crate::throttle::Throttle;
+
+const BUFFER_SIZE: usize = 8192;
+
+//Below this size, threads cost more than they save and pairs are compared sequentially
+const PARALLEL_MIN_SIZE: u64 = 64 * 1024 * 1024;
+//Bytes compared by a thread between two looks at the differences found by the others
+const PARALLEL_BLOCK: usize = 1024 * 1024;
+
+//Files of a class being compared by `identical`, each with its reader
+type OpenClass = Vec<(DirEntry, BufReader<fs::File>)>;
+
+/*
+ * Near-equality of same sized files: two files match when they differ in at most
+ * `max_diff` bytes. Every file is compared against the first member of each cluster
+ * already built, so a class of n files costs up to n² / 2 full comparisons.
+ */
+pub fn clusters(files: Vec<DirEntry>, max_diff: u64, throttle: &mut Throttle) -> Result<Vec<Vec<DirEntry>>, Error> {
+    let mut clusters : Vec<Vec<DirEntry>> = Vec::new();
+    for file in files.into_iter() {
+        let mut found = None;
+        for (i, cluster) in clusters.iter().enumerate() {
+            if differences(cluster[0].path(), file.path(), max_diff, throttle)?.is_some() {
+                found = Some(i);
+                break;
+            }
+        }
+        match found {
+            Some(i) => clusters[i].push(file),
+            None => clusters.push(vec![file]),
+        }
+    }
+    Ok(clusters)
+}
+
+/*
+ * Exact equality of files of the same size, read all at once in lockstep: after every
+ * block the files are split on its content and a class alone in its content isn't read
+ * any further, so files differing early cost one block. Every class is returned, the
+ * ones of a single file included. Each file is kept open until its class is settled.
+ */
+pub fn identical(files: Vec<DirEntry>, throttle: &mut Throttle) -> Result<Vec<Vec<DirEntry>>, Error> {
+    //A pair of big files is settled at once by first_difference
+    let big = |f: &DirEntry| f.metadata().is_ok_and(|m| parallel(m.len(), throttle));
+    if files.len() == 2 && files.iter().all(big) {
+        return match compare(files[0].path(), files[1].path(), throttle)? {
+            Some(None) => Ok(vec![files]),
+            Some(Some(_)) => Ok(files.into_iter().map(|f| vec![f]).collect()),
+            //The other one is still a class of its own
+            None => Ok(files.into_iter().filter(|f| f.path().exists()).map(|f| vec![f]).collect()),
+        };
+    }
+    let mut class : OpenClass = Vec::new();
+    for file in files.into_iter() {
+        if let Some(reader) = find::unless_vanished(file.path(), fs::File::open(file.path()))? {
+            class.push((file, BufReader::new(reader)));
+        }
+    }
+    let mut open = vec![class];
+    let mut settled = Vec::new();
+    while let Some(class) = open.pop() {
+        if class.len() < 2 {
+            settled.push(class.into_iter().map(|f| f.0).collect());
+            continue
+        }
+        let mut blocks : Vec<(Vec<u8>, OpenClass)> = Vec::new();
+        for (file, mut reader) in class.into_iter() {
+            let mut buffer = vec![0u8; BUFFER_SIZE];
+            let n = read_full(&mut reader, &mut buffer)?;
+            throttle.consume(n);
+            buffer.truncate(n);
+            match blocks.iter_mut().find(|b| b.0 == buffer) {
+                Some(block) => block.1.push((file, reader)),
+                None => blocks.push((buffer, vec![(file, reader)])),
+            }
+        }
+        for (block, class) in blocks.into_iter() {
+            //Every file of the class ended at once, with the same content all along
+            if block.is_empty() {
+                settled.push(class.into_iter().map(|f| f.0).collect());
+            } else {
+                open.push(class);
+            }
+        }
+    }
+    Ok(settled)
+}
+
+//Whether a pair of files of this size is settled faster by first_difference than by hashing
+pub fn parallel(size: u64, throttle: &Throttle) -> bool {
+    size >= PARALLEL_MIN_SIZE && !throttle.limited()
+}
+
+/*
+ * first_difference of a pair, the offset found told under --verbose. None when one of
+ * the files vanished, which is told too.
+ */
+pub fn compare(a: &Path, b: &Path, throttle: &mut Throttle) -> Result<Option<Option<u64>>, Error> {
+    match first_difference(a, b, throttle) {
+        Ok(found) => {
+            if let (Some(offset), true) = (found, find::verbose()) {
+                eprintln!("differ: {} and {} from byte {}", a.to_string_lossy(), b.to_string_lossy(), offset);
+            }
+            Ok(Some(found))
+        },
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            for path in [a, b].iter().filter(|p| !p.exists()) {
+                find::vanished(path);
+            }
+            Ok(None)
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/*
+ * Offset of the first byte differing between two files, None when they are identical.
+ * Big files are memory mapped and split in one range per core, all compared at once,
+ * which pays off on fast storage. Smaller files, a limited read rate or files that
+ * can't be mapped are compared with sequential reads.
+ * A mapped file truncated during the comparison can kill the process (SIGBUS).
+ */
+pub fn first_difference(a: &Path, b: &Path, throttle: &mut Throttle) -> Result<Option<u64>, Error> {
+    let file_a = fs::File::open(a)?;
+    let file_b = fs::File::open(b)?;
+    let size = file_a.metadata()?.len().min(file_b.metadata()?.len());
+    if parallel(size, throttle) {
+        if let Ok((map_a, map_b)) = unsafe { Mmap::map(&file_a).and_then(|m| Ok((m, Mmap::map(&file_b)?))) } {
+            let found = mapped_difference(&map_a, &map_b);
+            throttle.consume(2 * found.unwrap_or(size) as usize);
+            return Ok(found);
+        }
+    }
+    let mut reader_a = BufReader::new(file_a);
+    let mut reader_b = BufReader::new(file_b);
+    let mut buffer_a = [0u8; BUFFER_SIZE];
+    let mut buffer_b = [0u8; BUFFER_SIZE];
+    let mut offset = 0;
+    loop {
+        let n = read_full(&mut reader_a, &mut buffer_a)?;
+        let m = read_full(&mut reader_b, &mut buffer_b)?;
+        throttle.consume(n + m);
+        if let Some(i) = buffer_a[..n.min(m)].iter().zip(buffer_b[..n.min(m)].iter()).position(|(x, y)| x != y) {
+            return Ok(Some(offset + i as u64));
+        }
+        if n != m {
+            return Ok(Some(offset + n.min(m) as u64));
+        }
+        if n == 0 {
+            return Ok(None);
+        }
+        offset += n as u64;
+    }
+}
+
+fn mapped_difference(a: &[u8], b: &[u8]) -> Option<u64> {
+    let common = a.len().min(b.len());
+    let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let range = common.div_ceil(threads).max(1);
+    //Lowest offset found differing so far, the ranges past it give up
+    let found = AtomicUsize::new(usize::MAX);
+    thread::scope(|scope| {
+        for start in (0..common).step_by(range) {
+            let found = &found;
+            scope.spawn(move || {
+                let end = (start + range).min(common);
+                let mut offset = start;
+                while offset < end && offset < found.load(Ordering::Relaxed) {
+                    let block = (offset + PARALLEL_BLOCK).min(end);
+                    if a[offset..block] != b[offset..block] {
+                        if let Some(i) = a[offset..block].iter().zip(&b[offset..block]).position(|(x, y)| x != y) {
+                            found.fetch_min(offset + i, Ordering::Relaxed);
+                        }
+                        return;
+                    }
+                    offset = block;
+                }
+            });
+        }
+    });
+    match found.into_inner() {
+        usize::MAX if a.len() == b.len() => None,
+        usize::MAX => Some(common as u64),
+        offset => Some(offset as u64),
+    }
+}
+
+//Number of differing bytes between two files of the same size, None as soon as it exceeds the limit
+fn differences(a: &Path, b: &Path, limit: u64, throttle: &mut Throttle) -> Result<Option<u64>, Error> {
+    let mut reader_a = BufReader::new(fs::File::open(a)?);
+    let mut reader_b = BufReader::new(fs::File::open(b)?);
+    let mut buffer_a = [0u8; BUFFER_SIZE];
+    let mut buffer_b = [0u8; BUFFER_SIZE];
+    let mut count = 0;
+    loop {
+        let n = read_full(&mut reader_a, &mut buffer_a)?;
+        let m = read_full(&mut reader_b, &mut buffer_b)?;
+        throttle.consume(n + m);
+        count += buffer_a[..n.min(m)].iter().zip(buffer_b[..n.min(m)].iter()).filter(|(x, y)| x != y).count() as u64;
+        //Files were the same size when listed, any length difference now counts as differing bytes
+        count += n.abs_diff(m) as u64;
+        if count > limit {
+            return Ok(None);
+        }
+        if n == 0 && m == 0 {
+            return Ok(Some(count));
+        }
+    }
+}
+
+//Fill the buffer as much as possible so both files stay aligned on their offsets
+fn read_full<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize, Error> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        let n = reader.read(&mut buffer[filled..])?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_difference_offset() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b, c, d) = (dir.path().join("a"), dir.path().join("b"), dir.path().join("c"), dir.path().join("d"));
+        let content = vec![7u8; 3 * BUFFER_SIZE];
+        let mut changed = content.clone();
+        changed[BUFFER_SIZE + 5] = 8;
+        fs::write(&a, &content).unwrap();
+        fs::write(&b, &content).unwrap();
+        fs::write(&c, &changed).unwrap();
+        fs::write(&d, &content[..BUFFER_SIZE]).unwrap();
+        let mut throttle = Throttle::new(None);
+        assert_eq!(first_difference(&a, &b, &mut throttle).unwrap(), None);
+        assert_eq!(first_difference(&a, &c, &mut throttle).unwrap(), Some(BUFFER_SIZE as u64 + 5));
+        assert_eq!(first_difference(&a, &d, &mut throttle).unwrap(), Some(BUFFER_SIZE as u64));
+        assert_eq!(compare(&a, &dir.path().join("gone"), &mut throttle).unwrap(), None);
+    }
+
+    #[test]
+    fn mapped_difference_offset() {
+        let a = vec![0u8; 10 * PARALLEL_BLOCK];
+        let mut b = a.clone();
+        assert_eq!(mapped_difference(&a, &b), None);
+        b[7 * PARALLEL_BLOCK + 3] = 1;
+        b[9 * PARALLEL_BLOCK] = 1;
+        assert_eq!(mapped_difference(&a, &b), Some(7 * PARALLEL_BLOCK as u64 + 3));
+        assert_eq!(mapped_difference(&a, &a[..PARALLEL_BLOCK]), Some(PARALLEL_BLOCK as u64));
+    }
+}
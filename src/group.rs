@@ -0,0 +1,146 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+
+//Mode and owner of a file, only known on unix platforms
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ownership {
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Ownership {
+    #[cfg(unix)]
+    pub fn of(path: &Path) -> Option<Ownership> {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = fs::metadata(path).ok()?;
+        Some(Ownership {
+            //permission bits only, the file type is the same for every member
+            mode: metadata.mode() & 0o7777,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn of(_path: &Path) -> Option<Ownership> {
+        None
+    }
+}
+
+/*
+ * A set of files found to be duplicates of each other.
+ * Name is always filled, size and hash only when the comparison mode used them.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DuplicateGroup {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    pub files: Vec<PathBuf>,
+    //File found to be a truncated copy of the other member, only set by `n --truncated`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<PathBuf>,
+    //Percentage of the smaller file found in the other one, only set by the c subcommand
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overlap: Option<f64>,
+    //Ownership of each member of `files`, in the same order, only set by `h --ownership`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ownership: Option<Vec<Ownership>>,
+    //Label of the report each member of `files` comes from, set by `merge`, or its scan root with --relativize-to-root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sources: Option<Vec<String>>,
+    //Some content couldn't be read and was hashed as zeros, only set by `h --tolerant-read`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub suspect: bool,
+    //Members whose content was compared once decompressed, only set by `h --decompress`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub compressed: Vec<PathBuf>,
+    //Member an action would keep under the --keep choice, only set by plain `h` (see action::survivor)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub survivor: Option<PathBuf>,
+    //Files under each member, which are directories holding the same tree, only set by `h --directories`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub directory_files: Option<u64>,
+    //One of several groups made out of the same key, set by --fuzzy-bytes, --verify and --match-xattr
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub split: bool,
+    //Stable identifier of the group (see stable_id), set in the JSON output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+}
+
+impl DuplicateGroup {
+    pub fn new(name: String, size: Option<u64>, hash: Option<String>, files: Vec<PathBuf>) -> DuplicateGroup {
+        DuplicateGroup {
+            name,
+            size,
+            hash,
+            files,
+            truncated: None,
+            overlap: None,
+            ownership: None,
+            sources: None,
+            suspect: false,
+            compressed: Vec::new(),
+            survivor: None,
+            directory_files: None,
+            split: false,
+            id: None,
+        }
+    }
+
+    /*
+     * Identifier of the group that stays the same from one run and one machine to the
+     * other as long as its key does: the first 16 hex digits of the sha256 of the name,
+     * size and hash compared, each followed by a newline (an empty line when missing).
+     * Pairs of `c` and `n --truncated`, keyed by their members, add their sorted paths, as
+     * do the groups split out of one key, which it doesn't tell apart.
+     */
+    pub fn stable_id(&self) -> String {
+        let mut key = Sha256::new();
+        key.update(format!("{}\n", self.name));
+        key.update(format!("{}\n", self.size.map(|s| s.to_string()).unwrap_or_default()));
+        key.update(format!("{}\n", self.hash.as_deref().unwrap_or_default()));
+        if self.overlap.is_some() || self.truncated.is_some() || self.split {
+            let mut files : Vec<&PathBuf> = self.files.iter().collect();
+            files.sort();
+            for f in files.into_iter() {
+                key.update(format!("{}\n", f.to_string_lossy()));
+            }
+        }
+        format!("{:x}", key.finalize())[..16].to_string()
+    }
+
+    /*
+     * Fill `ownership` from the members. Left unset when any member can't be read,
+     * which is always the case where ownership isn't available.
+     */
+    pub fn annotate_ownership(&mut self) {
+        self.ownership = self.files.iter().map(|f| Ownership::of(f)).collect();
+    }
+
+    //Fill `sources` with the root each member was found under, the deepest one when roots nest
+    pub fn annotate_roots(&mut self, roots: &[PathBuf]) {
+        let sources = self.files.iter().map(|f| {
+            roots.iter()
+                .filter(|r| f.starts_with(r))
+                .max_by_key(|r| r.components().count())
+                .map(|r| r.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        });
+        self.sources = Some(sources.collect());
+    }
+
+    //Identical contents with different modes or owners, likely a permissions mistake
+    pub fn ownership_differs(&self) -> bool {
+        match &self.ownership {
+            Some(ownership) => ownership.iter().any(|o| *o != ownership[0]),
+            None => false,
+        }
+    }
+}
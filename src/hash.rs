@@ -0,0 +1,125 @@
+use std::fs;
+use std::io::{BufReader, Error, ErrorKind, Read, Seek, SeekFrom};
+use std::path::Path;
+use sha2::{Digest, Sha256};
+use digest::generic_array::GenericArray;
+use crate::throttle::Throttle;
+
+//Digest type produced by the hash processing
+pub type Hash = GenericArray<u8, <Sha256 as Digest>::OutputSize>;
+
+//Buffer size for the hash processing
+pub const BUFFER_SIZE: usize = 1024;
+
+//Region given up on a read error by hash_file_tolerant
+const BAD_REGION_SIZE: u64 = 4096;
+
+pub fn hash_file(path: &Path, throttle: &mut Throttle) -> Result<Hash, Error> {
+    let file = fs::File::open(path)?;
+    let mut reader = BufReader::new(file);
+    process::<Sha256,_>(&mut reader, throttle)
+}
+
+/*
+ * Hash of the first and last `bytes` bytes of the file, the whole file when these overlap.
+ * Much cheaper than hash_file on big files but only tells candidates apart.
+ */
+pub fn hash_ends(path: &Path, bytes: u64, throttle: &mut Throttle) -> Result<Hash, Error> {
+    let head = fs::File::open(path)?;
+    let size = head.metadata()?.len();
+    if size <= bytes.saturating_mul(2) {
+        return hash_file(path, throttle);
+    }
+    let mut tail = fs::File::open(path)?;
+    tail.seek(SeekFrom::Start(size - bytes))?;
+    let mut reader = BufReader::new(head.take(bytes).chain(tail));
+    process::<Sha256,_>(&mut reader, throttle)
+}
+
+//Hash of the file past its first `offset` bytes
+pub fn hash_from(path: &Path, offset: u64, throttle: &mut Throttle) -> Result<Hash, Error> {
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut reader = BufReader::new(file);
+    process::<Sha256,_>(&mut reader, throttle)
+}
+
+//Hash of the `len` bytes of the file from `start` on, fewer past its end
+pub fn hash_range(path: &Path, start: u64, len: u64, throttle: &mut Throttle) -> Result<Hash, Error> {
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut reader = BufReader::new(file.take(len));
+    process::<Sha256,_>(&mut reader, throttle)
+}
+
+/*
+ * Sha256 of the content without the zero bytes it ends with, at most `limit` of them:
+ * the tail is read first to find where the zeros start, then the content up to there.
+ */
+pub fn hash_unpadded(path: &Path, limit: u64, throttle: &mut Throttle) -> Result<Hash, Error> {
+    let mut file = fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    let tail_size = limit.min(size);
+    let mut tail = vec![0u8; tail_size as usize];
+    file.seek(SeekFrom::Start(size - tail_size))?;
+    file.read_exact(&mut tail)?;
+    throttle.consume(tail.len());
+    let zeros = tail.iter().rev().take_while(|&&b| b == 0).count() as u64;
+    file.seek(SeekFrom::Start(0))?;
+    let mut reader = BufReader::new(file.take(size - zeros));
+    process::<Sha256,_>(&mut reader, throttle)
+}
+
+/*
+ * Same as hash_file, except that a read error doesn't fail: the region is skipped and
+ * hashed as zeros, so the digest only matches other copies damaged the same way.
+ * Returns the offsets of the regions skipped along the digest.
+ */
+pub fn hash_file_tolerant(path: &Path, throttle: &mut Throttle) -> Result<(Hash, Vec<u64>), Error> {
+    let mut file = fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut sh = Sha256::default();
+    let mut buffer = [0u8; BUFFER_SIZE];
+    let mut offset = 0;
+    let mut bad = Vec::new();
+    while offset < size {
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => {
+                throttle.consume(n);
+                sh.update(&buffer[..n]);
+                offset += n as u64;
+            },
+            Err(e) if e.kind() == ErrorKind::Interrupted => {},
+            Err(_) => {
+                let skipped = BAD_REGION_SIZE.min(size - offset);
+                bad.push(offset);
+                sh.update(&[0u8; BAD_REGION_SIZE as usize][..skipped as usize]);
+                offset += skipped;
+                file.seek(SeekFrom::Start(offset))?;
+            },
+        }
+    }
+    Ok((sh.finalize(), bad))
+}
+
+/*
+ * provided by https://github.com/RustCrypto/hashes/blob/master/sha2/examples/sha256sum.rs
+ * Only a read of 0 bytes is the end: a short read isn't, a chain or a take returns one
+ * at every boundary.
+ */
+pub fn process<D: Digest + Default, R: Read>(reader: &mut R, throttle: &mut Throttle) -> Result<GenericArray<u8, <D as Digest>::OutputSize>, Error>{
+    let mut sh = D::default();
+    let mut buffer = [0u8; BUFFER_SIZE];
+    loop {
+        let n = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        throttle.consume(n);
+        sh.update(&buffer[..n]);
+    }
+    Ok(sh.finalize())
+}
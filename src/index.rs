@@ -0,0 +1,401 @@
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use rusqlite::{params, Connection, OpenFlags};
+use tempfile::TempPath;
+use walkdir::DirEntry;
+use crate::deadline::Deadline;
+use crate::find::{self, HashOptions};
+use crate::group::DuplicateGroup;
+use crate::hash;
+use crate::manifest::Manifest;
+use crate::options::CachePolicy;
+use crate::throttle::Throttle;
+
+//Rows hashed or read back per query, so memory doesn't grow with the tree; the hashes
+//of a batch are committed together, a run killed only loses the batch it was in
+const BATCH_SIZE: i64 = 1000;
+
+/*
+ * Next batch of files to hash: the ones of the candidate buckets without a hash, from
+ * rowid ?1 on. The rows are walked in rowid order from where the last batch stopped;
+ * CROSS JOIN and +seen keep SQLite from going through files_key and sorting every row.
+ */
+const TO_HASH: &str = "
+    SELECT files.rowid, files.path FROM files CROSS JOIN temp.candidates USING (name, size)
+    WHERE +files.seen = 1 AND files.hash IS NULL AND files.rowid > ?1
+    ORDER BY files.rowid LIMIT ?2
+";
+
+//One kept hash in this many is checked again under CachePolicy::Verify
+const VERIFY_SAMPLE: i64 = 100;
+
+/*
+ * On-disk state of the h comparison: one row per file with its name key, size, mtime
+ * and hash once computed. Grouping is done by queries instead of maps, which lets trees
+ * of tens of millions of files be scanned in bounded memory.
+ * The rows stay between runs: a file whose size and mtime didn't change keeps its hash,
+ * as far as the cache policy allows.
+ */
+pub struct Index {
+    connection: Connection,
+    policy: CachePolicy,
+}
+
+fn sql_error(e: rusqlite::Error) -> Error {
+    Error::other(e)
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(unix)]
+fn bytes_path(bytes: Vec<u8>) -> PathBuf {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(not(unix))]
+fn bytes_path(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn mtime(entry: &DirEntry) -> Result<Option<i64>, Error> {
+    let metadata = match find::unless_vanished(entry.path(), entry.metadata())? {
+        Some(metadata) => metadata,
+        None => return Ok(None),
+    };
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as i64).unwrap_or(0);
+    Ok(Some(modified))
+}
+
+//Table of the files, created in `database`
+fn schema(database: &str) -> String {
+    format!("
+        CREATE TABLE IF NOT EXISTS {database}.files (
+            path BLOB PRIMARY KEY,
+            name TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            mtime INTEGER NOT NULL,
+            hash TEXT,
+            seen INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE INDEX IF NOT EXISTS {database}.files_key ON files (seen, name, size, hash);
+    ", database=database)
+}
+
+impl Index {
+    /*
+     * With CachePolicy::Ignore the database is opened read-only: the rows of the run go to
+     * a temporary table of the same name, which the queries find first, and only the hashes
+     * kept are read from the file.
+     */
+    pub fn open(path: &Path, policy: CachePolicy) -> Result<Index, Error> {
+        let connection = match policy {
+            CachePolicy::Ignore if path.exists() => Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY),
+            CachePolicy::Ignore => Connection::open_in_memory(),
+            _ => Connection::open(path),
+        }.map_err(sql_error)?;
+        if policy != CachePolicy::Ignore || !path.exists() {
+            connection.execute_batch(&schema("main")).map_err(sql_error)?;
+        }
+        if policy == CachePolicy::Ignore {
+            connection.execute_batch(&schema("temp")).map_err(sql_error)?;
+        }
+        Ok(Index { connection, policy })
+    }
+
+    //Record the walked files, forgetting the hash of the ones changed since it was computed
+    fn insert<I>(&mut self, iter: I, options: HashOptions) -> Result<(), Error>
+    where I: IntoIterator<Item= DirEntry>,
+    {
+        let transaction = self.connection.transaction().map_err(sql_error)?;
+        transaction.execute("UPDATE files SET seen = 0", []).map_err(sql_error)?;
+        {
+            //The temporary table of CachePolicy::Ignore starts empty, the hashes kept come from the file
+            let kept = if self.policy == CachePolicy::Ignore {
+                "(SELECT hash FROM main.files WHERE path = ?1 AND size = ?3 AND mtime = ?4)"
+            } else {
+                "NULL"
+            };
+            let mut upsert = transaction.prepare(&format!("
+                INSERT INTO files (path, name, size, mtime, hash, seen) VALUES (?1, ?2, ?3, ?4, {}, 1)
+                ON CONFLICT (path) DO UPDATE SET
+                    hash = CASE WHEN size = excluded.size AND mtime = excluded.mtime THEN hash END,
+                    name = excluded.name, size = excluded.size, mtime = excluded.mtime, seen = 1
+            ", kept)).map_err(sql_error)?;
+            for entry in iter.into_iter() {
+                let f_size = match find::unless_vanished(entry.path(), entry.metadata())? {
+                    Some(metadata) => metadata.len(),
+                    None => continue,
+                };
+                if options.skips(f_size) {
+                    continue
+                }
+                let f_mtime = match mtime(&entry)? {
+                    Some(mtime) => mtime,
+                    None => continue,
+                };
+                upsert.execute(params![path_bytes(entry.path()), options.key_name(&entry), f_size as i64, f_mtime]).map_err(sql_error)?;
+            }
+        }
+        transaction.commit().map_err(sql_error)
+    }
+
+    /*
+     * Rehash a sample of the hashes kept from earlier runs. A changed one means a file
+     * got new content without a new size or mtime: it is warned about loudly and fixed,
+     * although the rest of the kept hashes can't be trusted either.
+     */
+    fn verify(&mut self, throttle: &mut Throttle, deadline: &Deadline) -> Result<(), Error> {
+        let sample : Vec<(i64, PathBuf, String)> = {
+            let mut select = self.connection.prepare("
+                SELECT rowid, path, hash FROM files
+                WHERE seen = 1 AND hash IS NOT NULL AND abs(random()) % ?1 = 0
+            ").map_err(sql_error)?;
+            let rows = select.query_map(params![VERIFY_SAMPLE], |row| Ok((row.get(0)?, bytes_path(row.get(1)?), row.get(2)?))).map_err(sql_error)?;
+            rows.collect::<Result<_, _>>().map_err(sql_error)?
+        };
+        let mut stale = 0;
+        for (rowid, path, kept) in sample.iter() {
+            if deadline.expired() {
+                break
+            }
+            let f_hash = match find::unless_vanished(path, hash::hash_file(path, throttle))? {
+                Some(f_hash) => format!("{:x}", f_hash),
+                None => continue,
+            };
+            if &f_hash != kept {
+                stale += 1;
+                eprintln!("warning: kept hash of {} is stale, its content changed but not its size and mtime", path.to_string_lossy());
+                self.connection.execute("UPDATE files SET hash = ?1 WHERE rowid = ?2", params![f_hash, rowid]).map_err(sql_error)?;
+            }
+        }
+        if stale != 0 {
+            eprintln!("warning: {} of {} kept hashes checked were stale, mtimes can't be relied on in this tree and the index should be rebuilt", stale, sample.len());
+        }
+        Ok(())
+    }
+
+    //Hash the files that need it, returns false when the deadline stopped it
+    fn hash(&mut self, options: HashOptions, listed: bool, throttle: &mut Throttle, deadline: &Deadline) -> Result<bool, Error> {
+        //Alone in its bucket a file can't have a duplicate, it only needs hashing to be reported unique or listed
+        let minimum = if options.unique || listed { 1 } else { 2 };
+        //The buckets worth hashing are counted once, each batch then only looks them up
+        self.connection.execute_batch("
+            DROP TABLE IF EXISTS temp.candidates;
+            CREATE TABLE temp.candidates (name TEXT NOT NULL, size INTEGER NOT NULL, PRIMARY KEY (name, size)) WITHOUT ROWID;
+        ").map_err(sql_error)?;
+        self.connection.execute("
+            INSERT INTO temp.candidates SELECT name, size FROM files WHERE seen = 1 GROUP BY name, size HAVING count(*) >= ?1
+        ", params![minimum]).map_err(sql_error)?;
+        let mut last = 0;
+        loop {
+            let batch : Vec<(i64, PathBuf)> = {
+                let mut select = self.connection.prepare(TO_HASH).map_err(sql_error)?;
+                let rows = select.query_map(params![last, BATCH_SIZE], |row| Ok((row.get(0)?, bytes_path(row.get(1)?)))).map_err(sql_error)?;
+                rows.collect::<Result<_, _>>().map_err(sql_error)?
+            };
+            if batch.is_empty() {
+                return Ok(true);
+            }
+            let transaction = self.connection.transaction().map_err(sql_error)?;
+            for (rowid, path) in batch.into_iter() {
+                if deadline.expired() {
+                    transaction.commit().map_err(sql_error)?;
+                    return Ok(false);
+                }
+                last = rowid;
+                match find::unless_vanished(&path, find::retrying(options.retries, throttle, |throttle| hash::hash_file(&path, throttle)))? {
+                    Some(f_hash) => transaction.execute("UPDATE files SET hash = ?1 WHERE rowid = ?2", params![format!("{:x}", f_hash), rowid]),
+                    None => transaction.execute("UPDATE files SET seen = 0 WHERE rowid = ?1", params![rowid]),
+                }.map_err(sql_error)?;
+            }
+            transaction.commit().map_err(sql_error)?;
+        }
+    }
+
+    //Stream the groups of the hashed files, one (name, size, hash) at a time, listing every file in the manifest
+    fn groups<F>(&self, options: HashOptions, mut manifest: Option<&mut Manifest>, mut emit: F) -> Result<(), Error>
+    where F: FnMut(DuplicateGroup) -> Result<(), Error>,
+    {
+        let mut select = self.connection.prepare("
+            SELECT name, size, hash, path FROM files
+            WHERE seen = 1 AND hash IS NOT NULL
+            ORDER BY name, size, hash
+        ").map_err(sql_error)?;
+        let mut rows = select.query([]).map_err(sql_error)?;
+        let mut current : Option<DuplicateGroup> = None;
+        let mut flush = |group: Option<DuplicateGroup>| -> Result<(), Error> {
+            match group {
+                Some(group) if (group.files.len() == 1) == options.unique => emit(group),
+                _ => Ok(()),
+            }
+        };
+        while let Some(row) = rows.next().map_err(sql_error)? {
+            let name : String = row.get(0).map_err(sql_error)?;
+            let size : i64 = row.get(1).map_err(sql_error)?;
+            let f_hash : String = row.get(2).map_err(sql_error)?;
+            let path = bytes_path(row.get(3).map_err(sql_error)?);
+            if let Some(manifest) = manifest.as_mut() {
+                manifest.record(&path, &f_hash)?;
+            }
+            let same = current.as_ref().is_some_and(|g| g.name == name && g.size == Some(size as u64) && g.hash.as_ref() == Some(&f_hash));
+            if same {
+                if let Some(group) = current.as_mut() {
+                    group.files.push(path);
+                }
+            } else {
+                flush(current.take())?;
+                current = Some(DuplicateGroup::new(name, Some(size as u64), Some(f_hash), vec![path]));
+            }
+        }
+        flush(current.take())
+    }
+}
+
+/*
+ * Index in a file of the temporary directory, for a run that outgrew its memory limit:
+ * none of its rows are worth keeping, the file is removed once the index is dropped.
+ * The file is created under a random name, never one an other process made first.
+ */
+pub struct TemporaryIndex {
+    index: Option<Index>,
+    file: Option<TempPath>,
+}
+
+impl TemporaryIndex {
+    pub fn create() -> Result<TemporaryIndex, Error> {
+        let file = tempfile::Builder::new().prefix("rustadup-").suffix(".sqlite").tempfile()?.into_temp_path();
+        let index = Index::open(&file, CachePolicy::Trust)?;
+        Ok(TemporaryIndex { index: Some(index), file: Some(file) })
+    }
+
+    pub fn index(&mut self) -> &mut Index {
+        self.index.as_mut().expect("index is only taken on drop")
+    }
+}
+
+impl Drop for TemporaryIndex {
+    fn drop(&mut self) {
+        //Closed before the removal, which some platforms refuse on an open file
+        self.index = None;
+        if let Some(file) = self.file.take() {
+            let _ = file.close();
+        }
+    }
+}
+
+/*
+ * Same comparison as find::for_each_by_hashes, with the grouping done in the index.
+ * Groups only come once every file needing it was hashed, after a timeout the certain
+ * duplicates are still reported but unique files aren't.
+ * With a manifest every file is hashed and written to it, kept hashes included.
+ */
+pub fn for_each_by_hashes<I, F>(iter: I, index: &mut Index, options: HashOptions, manifest: Option<&mut Manifest>, throttle: &mut Throttle, deadline: &Deadline, emit: F) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    index.insert(iter, options)?;
+    if index.policy == CachePolicy::Verify {
+        index.verify(throttle, deadline)?;
+    }
+    let complete = index.hash(options, manifest.is_some(), throttle, deadline)?;
+    if complete || !options.unique {
+        index.groups(options, manifest, emit)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn groups(root: &Path, index: &mut Index) -> Vec<DuplicateGroup> {
+        let iter = walkdir::WalkDir::new(root).into_iter().filter_map(Result::ok).filter(|e| e.file_type().is_file());
+        find::collect(|emit| for_each_by_hashes(iter, index, HashOptions::default(), None, &mut Throttle::new(None), &Deadline::default(), emit)).unwrap()
+    }
+
+    #[test]
+    fn ignore_reads_the_kept_hashes_and_writes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let tree = dir.path().join("tree");
+        for member in ["x", "y"] {
+            fs::create_dir_all(tree.join(member)).unwrap();
+            fs::write(tree.join(member).join("a"), "same").unwrap();
+        }
+        let database = dir.path().join("files.db");
+        groups(&tree, &mut Index::open(&database, CachePolicy::Trust).unwrap());
+        Connection::open(&database).unwrap().execute("UPDATE files SET hash = 'kept'", []).unwrap();
+        let before = fs::read(&database).unwrap();
+
+        let found = groups(&tree, &mut Index::open(&database, CachePolicy::Ignore).unwrap());
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].hash.as_deref(), Some("kept"));
+        assert_eq!(fs::read(&database).unwrap(), before);
+    }
+
+    #[test]
+    fn temporary_indexes_are_apart_and_removed() {
+        let first = TemporaryIndex::create().unwrap();
+        let second = TemporaryIndex::create().unwrap();
+        let paths = [first.file.as_ref().unwrap().to_path_buf(), second.file.as_ref().unwrap().to_path_buf()];
+        assert_ne!(paths[0], paths[1]);
+        drop(first);
+        drop(second);
+        assert!(paths.iter().all(|p| !p.exists()));
+    }
+
+    #[test]
+    fn batches_walk_the_rows_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let tree = dir.path().join("tree");
+        for (path, content) in [("x/a", "same"), ("y/a", "same"), ("y/b", "alone")] {
+            fs::create_dir_all(tree.join(path).parent().unwrap()).unwrap();
+            fs::write(tree.join(path), content).unwrap();
+        }
+        let mut index = Index::open(&dir.path().join("files.db"), CachePolicy::Trust).unwrap();
+        assert_eq!(groups(&tree, &mut index).len(), 1);
+        let unhashed : i64 = index.connection.query_row("SELECT count(*) FROM files WHERE hash IS NULL", [], |row| row.get(0)).unwrap();
+        assert_eq!(unhashed, 1);
+        let mut explain = index.connection.prepare(&format!("EXPLAIN QUERY PLAN {}", TO_HASH)).unwrap();
+        let plan : Vec<String> = explain.query_map(params![0, BATCH_SIZE], |row| row.get(3)).unwrap().map(Result::unwrap).collect();
+        assert!(plan[0].contains("rowid>?"), "{:?}", plan);
+        assert!(!plan.iter().any(|p| p.contains("B-TREE")), "{:?}", plan);
+    }
+
+    #[test]
+    fn a_run_cut_short_is_resumed() {
+        let dir = tempfile::tempdir().unwrap();
+        let tree = dir.path().join("tree");
+        for member in ["x", "y"] {
+            fs::create_dir_all(tree.join(member)).unwrap();
+            fs::write(tree.join(member).join("a"), [7u8; 400]).unwrap();
+        }
+        let database = dir.path().join("files.db");
+        let walk = || walkdir::WalkDir::new(&tree).into_iter().filter_map(Result::ok).filter(|e| e.file_type().is_file());
+        //At 1000 bytes a second the first file is hashed past the timeout, the second never is
+        let mut throttle = Throttle::new(Some(1000));
+        let deadline = Deadline::new(Some(std::time::Duration::from_millis(200)));
+        let mut index = Index::open(&database, CachePolicy::Trust).unwrap();
+        let found = find::collect(|emit| for_each_by_hashes(walk(), &mut index, HashOptions::default(), None, &mut throttle, &deadline, emit)).unwrap();
+        assert!(found.is_empty());
+        drop(index);
+
+        let mut throttle = Throttle::new(None);
+        let mut index = Index::open(&database, CachePolicy::Trust).unwrap();
+        let found = find::collect(|emit| for_each_by_hashes(walk(), &mut index, HashOptions::default(), None, &mut throttle, &Deadline::default(), emit)).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(throttle.bytes(), 400);
+    }
+}
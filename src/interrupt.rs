@@ -0,0 +1,81 @@
+use std::sync::atomic::{AtomicI32, AtomicPtr, AtomicU8, AtomicUsize, Ordering};
+
+//Nothing to close, the output wasn't begun or already ended
+const IDLE: u8 = 0;
+//Between two writes, the closing can follow what was written
+const BETWEEN: u8 = 1;
+//In the middle of a write, the closing has to wait for its end
+const WRITING: u8 = 2;
+
+static STATE: AtomicU8 = AtomicU8::new(IDLE);
+//Signal received during a write, 0 for none
+static PENDING: AtomicI32 = AtomicI32::new(0);
+static FD: AtomicI32 = AtomicI32::new(-1);
+static CLOSING: AtomicPtr<u8> = AtomicPtr::new(std::ptr::null_mut());
+static CLOSING_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/*
+ * Close the output and exit on a signal. Only write(2) and _exit(2) are called here,
+ * which are safe in a signal handler; the closing text was set beforehand.
+ */
+#[cfg(unix)]
+fn close_and_exit(signal: libc::c_int) -> ! {
+    let fd = FD.load(Ordering::SeqCst);
+    let closing = CLOSING.load(Ordering::SeqCst);
+    if STATE.load(Ordering::SeqCst) != IDLE && fd >= 0 && !closing.is_null() {
+        unsafe { libc::write(fd, closing as *const libc::c_void, CLOSING_LEN.load(Ordering::SeqCst)) };
+    }
+    unsafe { libc::_exit(128 + signal) }
+}
+
+#[cfg(unix)]
+extern "C" fn on_signal(signal: libc::c_int) {
+    if STATE.load(Ordering::SeqCst) == WRITING {
+        PENDING.store(signal, Ordering::SeqCst);
+        return;
+    }
+    close_and_exit(signal)
+}
+
+/*
+ * Have `closing` written to `fd` when SIGINT or SIGTERM stops the process while an
+ * output is begun, so what was written so far stays a valid document. The writer
+ * marks its writes with writing and written, a signal in between is held until the
+ * write ends. A single output can be guarded at a time. No-op on other platforms.
+ */
+#[cfg(unix)]
+pub fn close_on_signal(fd: i32, closing: &'static str) {
+    FD.store(fd, Ordering::SeqCst);
+    CLOSING_LEN.store(closing.len(), Ordering::SeqCst);
+    CLOSING.store(closing.as_ptr() as *mut u8, Ordering::SeqCst);
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn close_on_signal(_fd: i32, _closing: &'static str) {}
+
+//The output is begun, or a write reached its end: a signal now closes it
+pub fn written() {
+    STATE.store(BETWEEN, Ordering::SeqCst);
+    #[cfg(unix)]
+    {
+        let signal = PENDING.swap(0, Ordering::SeqCst);
+        if signal != 0 {
+            close_and_exit(signal);
+        }
+    }
+}
+
+//A write begins, a signal from now on waits for written
+pub fn writing() {
+    STATE.store(WRITING, Ordering::SeqCst);
+}
+
+//The output was closed, a signal has nothing left to write
+pub fn ended() {
+    STATE.store(IDLE, Ordering::SeqCst);
+}
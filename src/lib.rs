@@ -0,0 +1,51 @@
+/*
+ * Library side of rustadup: the comparison passes over an iterator of walked files.
+ *
+ * Every comparison comes in two flavors:
+ *  - find_by_* collects the duplicate groups into a Vec, which is the convenient choice
+ *    when the results are small or have to be sorted or serialized as a whole
+ *  - for_each_by_* hands every group to a callback as soon as it is final and keeps none
+ *    of them, so memory only holds the files still being compared
+ * The find_by_* functions are thin wrappers collecting what for_each_by_* streams.
+ *
+ * run::run and run::run_streaming drive a whole run (walk, filters, comparison and
+ * action) from an options::Options, and are the entry points the command line uses.
+ *
+ * The actions (action::plan, reflink, trash) go through a filesystem::FileSystem:
+ * filesystem::Disk changes the files for real, filesystem::Memory a tree in memory to
+ * check what an action does without touching a disk.
+ */
+pub mod action;
+pub mod baseline;
+pub mod chunked;
+pub mod command;
+pub mod deadline;
+pub mod decompress;
+pub mod diff;
+pub mod directories;
+pub mod fclones;
+pub mod filesystem;
+pub mod find;
+pub mod fuzzy;
+pub mod group;
+pub mod hash;
+pub mod index;
+pub mod interrupt;
+pub mod lock;
+pub mod manifest;
+pub mod media;
+pub mod merge;
+pub mod netfs;
+pub mod options;
+pub mod output;
+pub mod priority;
+pub mod report;
+pub mod run;
+pub mod selftest;
+pub mod size;
+pub mod template;
+pub mod throttle;
+pub mod tree;
+pub mod typecheck;
+pub mod verify;
+pub mod xattrs;
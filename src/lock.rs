@@ -0,0 +1,70 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+//Name of the lock file created in each root, never walked
+pub const LOCK_NAME: &str = ".rustadup.lock";
+
+/*
+ * Lock files held while an action changes files, so two runs given the same root (or the
+ * same lock path) can't act at the same time. The locks are per root, not per tree: a
+ * run over a directory inside a root locked by another one, or holding one, isn't kept
+ * out. Each lock file holds the id of the process owning it and is removed when the lock
+ * is dropped. A lock left by a crashed run stays until removed or taken over with `force`.
+ */
+pub struct Lock {
+    paths: Vec<PathBuf>,
+}
+
+//Whether the process that wrote the lock is gone, unknown where it can't be checked
+#[cfg(unix)]
+fn stale(pid: u32) -> Option<bool> {
+    let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    Some(result == -1 && Error::last_os_error().raw_os_error() == Some(libc::ESRCH))
+}
+
+#[cfg(not(unix))]
+fn stale(_pid: u32) -> Option<bool> {
+    None
+}
+
+fn held(path: &Path) -> Error {
+    let owner = fs::read_to_string(path).ok().and_then(|p| p.trim().parse::<u32>().ok());
+    let message = match owner.map(|pid| (pid, stale(pid))) {
+        Some((pid, Some(true))) => format!("{} is held by process {} which is gone, use --force-lock to take it over", path.to_string_lossy(), pid),
+        Some((pid, _)) => format!("{} is held by process {}, another run is acting on these files", path.to_string_lossy(), pid),
+        None => format!("{} is held by another run", path.to_string_lossy()),
+    };
+    Error::new(ErrorKind::AlreadyExists, message)
+}
+
+impl Lock {
+    //Take every lock or none: the ones already taken are released on failure
+    pub fn acquire(paths: Vec<PathBuf>, force: bool) -> Result<Lock, Error> {
+        let mut lock = Lock { paths: Vec::new() };
+        for path in paths.into_iter() {
+            if force {
+                match fs::remove_file(&path) {
+                    Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                    _ => {},
+                }
+            }
+            let mut file = match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => return Err(held(&path)),
+                Err(e) => return Err(e),
+            };
+            lock.paths.push(path);
+            writeln!(file, "{}", std::process::id())?;
+        }
+        Ok(lock)
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        for path in self.paths.iter() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
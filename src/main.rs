@@ -1,35 +1,76 @@
 use std::collections::HashMap;
-use std::io::Error;
-use walkdir::{WalkDir, DirEntry};
-use clap::{Arg, App, SubCommand};
 use std::fs;
-use std::io::{BufReader, Read};
-use sha2::{Digest, Sha256};
-use digest::generic_array::GenericArray;
+use std::io::{self, Error};
+use std::path::{Path, PathBuf};
+use walkdir::DirEntry;
+use clap::{Arg, ArgMatches, App, SubCommand, value_t};
+use regex::{Regex, RegexSet};
+
+use rustadup::action::{ActionStep, Risks};
+use rustadup::baseline::Baseline;
+use rustadup::deadline::Deadline;
+use rustadup::decompress;
+use rustadup::diff::{self, TreeDiff};
+use rustadup::fclones;
+use rustadup::find::{self, HashOptions};
+use rustadup::lock::{self, Lock};
+use rustadup::manifest;
+use rustadup::merge;
+use rustadup::options::{self, Action, CachePolicy, Filters, Keep, Marker, GroupPredicate, Mode, Options, OwnershipCheck};
+use rustadup::output::{self, Document, Format, Formatter, SplitOutput, TemplateFormatter, TextStyle, TimeFormat};
+use rustadup::group::DuplicateGroup;
+use rustadup::priority;
+use rustadup::report::{self, GroupStats, Report, Savings};
+use rustadup::run;
+use rustadup::selftest;
+use rustadup::size;
+use rustadup::template::Template;
+use rustadup::throttle::Throttle;
+use rustadup::typecheck;
+use rustadup::verify::{self, Mismatch};
 
 //Retrieve informations from Cargo.toml file
-const APPNAME: &'static str = env!("CARGO_PKG_NAME");
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
-const DESCRIPTION: &'static str = env!("CARGO_PKG_DESCRIPTION");
+const APPNAME: &str = env!("CARGO_PKG_NAME");
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const DESCRIPTION: &str = env!("CARGO_PKG_DESCRIPTION");
 
-//Buffer size for the hash processing
-const BUFFER_SIZE: usize = 1024;
+//Exit code when --timeout stopped the scan before the end
+const EXIT_TIMEOUT: i32 = 3;
+//Exit code when verify found groups that don't hold anymore
+const EXIT_VERIFY_FAILED: i32 = 4;
+//Exit code when the comparison found no group, like grep finding no match (1 is taken by errors)
+const EXIT_NO_GROUPS: i32 = 5;
+//Exit code when --max-files stopped the walk
+const EXIT_MAX_FILES: i32 = 6;
+//Exit code when selftest got a wrong digest
+const EXIT_SELFTEST_FAILED: i32 = 7;
 
-/*
- * Constants for hash skipping
- * TODO: provide the values through arguments
- */
-const SMALL_FILE_SIZE: u64 = 1024 * 1024 * 8; // 1 Mb
-const BIG_FILE_SIZE: u64 = 1024 * SMALL_FILE_SIZE; // 1 Gb
+//Options of h only meaningful along an --action changing files
+const ACTION_OPTIONS: &[&str] = &["dry-run", "lock", "force-lock", "include-risky", "risky-path", "risky-age", "action-log"];
+
+//What --count can print
+const COUNTS: &[&str] = &["files", "bytes", "groups", "duplicates", "unique"];
 
 fn main() -> Result<(), Error> {
     let app = App::new(APPNAME)
                     .version(VERSION)
                     .about(DESCRIPTION)
                     .subcommand(SubCommand::with_name("n")
-                        .about("Compare through file names only"))
+                        .about("Compare through file names only")
+                        .arg(normalize_arg())
+                        .arg(Arg::with_name("truncated")
+                            .long("truncated")
+                            .help("only report files whose content is the beginning of a same named file (partial downloads)"))
+                        .arg(Arg::with_name("two-pass")
+                            .long("two-pass")
+                            .conflicts_with("truncated")
+                            .help("walk the tree twice, first counting the names, so that only the repeated ones are kept in memory (huge flat directories)")))
                     .subcommand(SubCommand::with_name("s")
-                        .about("Compare through file names and sizes"))
+                        .about("Compare through file names and sizes")
+                        .arg(normalize_arg())
+                        .arg(Arg::with_name("confirm")
+                            .long("confirm")
+                            .help("hash the members of every group and only report the files with the same content, reading none of the files alone with their name and size")))
                     .subcommand(SubCommand::with_name("h")
                         .about("Compare through file hashes (using sha256, pretty slow)")
                         .arg(Arg::with_name("big-files")
@@ -37,106 +78,968 @@ fn main() -> Result<(), Error> {
                             .help("skip big files (> 1Gb)"))
                         .arg(Arg::with_name("small-files")
                             .short("s")
-                            .help("skip small files (< 1 Mb)")))
+                            .help("skip small files (< 1 Mb)"))
+                        .arg(Arg::with_name("unique")
+                            .short("u")
+                            .long("unique")
+                            .help("list the files that have no duplicate instead"))
+                        .arg(Arg::with_name("max-read-rate")
+                            .long("max-read-rate")
+                            .takes_value(true)
+                            .env("RUSTADUP_MAX_READ_RATE")
+                            .validator(size::is_size)
+                            .help("limit the reading speed while hashing, in bytes per second (e.g. 20M)"))
+                        .arg(Arg::with_name("retries")
+                            .long("retries")
+                            .takes_value(true)
+                            .validator(is_number)
+                            .help("read a file again up to N times, waiting longer each time, when it fails with a transient error (timeouts on network mounts)"))
+                        .arg(Arg::with_name("fuzzy-bytes")
+                            .long("fuzzy-bytes")
+                            .takes_value(true)
+                            .validator(is_number)
+                            .help("compare bytes directly and group files differing in at most N bytes (quadratic within each name and size class)"))
+                        .arg(Arg::with_name("ends")
+                            .long("ends")
+                            .takes_value(true)
+                            .validator(size::is_size)
+                            .conflicts_with("fuzzy-bytes")
+                            .help("only hash the first and last N bytes of each file (e.g. 1M), groups are candidates that may still differ in between"))
+                        .arg(Arg::with_name("skip-header")
+                            .long("skip-header")
+                            .takes_value(true)
+                            .validator(size::is_size)
+                            .conflicts_with_all(&["fuzzy-bytes", "ends", "tolerant-read", "verify", "decompress", "index", "action"])
+                            .help("leave the first N bytes of each file out of the hash, grouping files that only differ in a volatile header; groups are then not identical files"))
+                        .arg(Arg::with_name("range")
+                            .long("range")
+                            .takes_value(true)
+                            .value_name("START:LEN")
+                            .validator(size::is_range)
+                            .conflicts_with_all(&["fuzzy-bytes", "ends", "skip-header", "tolerant-read", "verify", "decompress", "index", "action", "checksum-output"])
+                            .help("only hash LEN bytes from START on in each file (e.g. 16:32), for formats identifying their content at a fixed place; shorter files are left out and groups are not identical files"))
+                        .arg(Arg::with_name("no-hints")
+                            .long("no-hints")
+                            .help("don't suggest cheaper comparisons before hashing a lot of data"))
+                        .arg(Arg::with_name("ignore-extension")
+                            .long("ignore-extension")
+                            .help("leave extensions out of the name compared, song.flac and song.flac.bak become song"))
+                        .arg(Arg::with_name("ownership")
+                            .long("ownership")
+                            .conflicts_with("unique")
+                            .help("list the mode, uid and gid of every member of the groups (unix only)"))
+                        .arg(Arg::with_name("ownership-differs")
+                            .long("ownership-differs")
+                            .conflicts_with("unique")
+                            .help("only report the groups whose members differ in mode, uid or gid, implies --ownership"))
+                        .arg(Arg::with_name("tolerant-read")
+                            .long("tolerant-read")
+                            .conflicts_with_all(&["fuzzy-bytes", "ends"])
+                            .help("go on hashing through read errors (bad sectors), the damaged files are flagged suspect and only group with each other"))
+                        .arg(Arg::with_name("verify")
+                            .long("verify")
+                            .conflicts_with_all(&["unique", "fuzzy-bytes"])
+                            .help("compare the members of each group byte for byte, all at once, and split the group where they differ (makes --ends groups certain)"))
+                        .arg(Arg::with_name("decompress")
+                            .long("decompress")
+                            .conflicts_with_all(&["fuzzy-bytes", "ends", "tolerant-read", "verify", "top", "action"])
+                            .help("hash .gz and .zst files on their decompressed content, so file.txt and file.txt.gz holding the same text are grouped"))
+                        .arg(Arg::with_name("media-streams")
+                            .long("media-streams")
+                            .conflicts_with_all(&["fuzzy-bytes", "ends", "skip-header", "range", "tolerant-read", "verify", "decompress", "index", "action", "checksum-output"])
+                            .help("group audio and video files holding the same streams in different containers (film.mkv and film.mp4), through ffmpeg; groups are media matches, not identical files"))
+                        .arg(Arg::with_name("ignore-trailing-zeros")
+                            .long("ignore-trailing-zeros")
+                            .conflicts_with_all(&["fuzzy-bytes", "ends", "skip-header", "range", "media-streams", "tolerant-read", "verify", "decompress", "index", "action", "checksum-output"])
+                            .help("leave the zeros files end with (up to 1M) out of the hash and the size out of the key, so zero-padded copies group with their originals; a heuristic, groups are not identical files"))
+                        .arg(Arg::with_name("match-hashes")
+                            .long("match-hashes")
+                            .takes_value(true)
+                            .value_name("FILE")
+                            .conflicts_with_all(&["fuzzy-bytes", "ends", "skip-header", "range", "media-streams", "ignore-trailing-zeros", "unique", "tolerant-read", "verify", "decompress", "index", "action", "checksum-output"])
+                            .help("only report the files whose sha256 is listed in FILE (one hex digest per line, or sha256sum lines), duplicated or not; every file gets hashed"))
+                        .arg(Arg::with_name("compare-cmd")
+                            .long("compare-cmd")
+                            .takes_value(true)
+                            .value_name("CMD")
+                            .conflicts_with_all(&["fuzzy-bytes", "ends", "skip-header", "range", "media-streams", "ignore-trailing-zeros", "match-hashes", "directories", "tolerant-read", "verify", "decompress", "index", "limit-memory", "action", "checksum-output"])
+                            .help("group the files of the same name for which the shell command CMD, given the file as last argument, prints the same output (e.g. 'jq -S .'); one process per file, groups are not identical files"))
+                        .arg(Arg::with_name("directories")
+                            .long("directories")
+                            .conflicts_with_all(&["fuzzy-bytes", "ends", "skip-header", "range", "media-streams", "ignore-trailing-zeros", "match-hashes", "big-files", "small-files", "unique", "tolerant-read", "verify", "decompress", "index", "limit-memory", "action", "checksum-output", "top"])
+                            .help("also group the directories holding the same tree of files, listed first, and leave out the file groups inside them; every file gets hashed"))
+                        .arg(Arg::with_name("index")
+                            .long("index")
+                            .takes_value(true)
+                            .value_name("PATH")
+                            .conflicts_with_all(&["fuzzy-bytes", "ends", "tolerant-read", "verify", "decompress"])
+                            .help("group through an SQLite database at PATH instead of memory, for huge trees; it keeps the hashes of unchanged files for the next runs"))
+                        .arg(Arg::with_name("limit-memory")
+                            .long("limit-memory")
+                            .takes_value(true)
+                            .value_name("SIZE")
+                            .validator(size::is_size)
+                            .conflicts_with_all(&["fuzzy-bytes", "ends", "skip-header", "range", "media-streams", "ignore-trailing-zeros", "match-hashes", "tolerant-read", "verify", "decompress", "index"])
+                            .help("group in a temporary SQLite database like --index once the files walked would take about SIZE of memory (e.g. 512M), with the same results"))
+                        .arg(Arg::with_name("cache-policy")
+                            .long("cache-policy")
+                            .takes_value(true)
+                            .possible_values(options::CACHE_POLICIES)
+                            .requires("index")
+                            .help("trust the hashes the index kept for files with unchanged size and mtime, verify a sample of them first (for tools that don't update mtimes), or ignore: use them without writing the database"))
+                        .arg(Arg::with_name("checksum-output")
+                            .long("checksum-output")
+                            .takes_value(true)
+                            .value_name("FILE")
+                            .conflicts_with_all(&["fuzzy-bytes", "ends", "skip-header", "decompress"])
+                            .help("also write the hash of every file to FILE in the format of sha256sum, for sha256sum -c; files with no possible duplicate get hashed too"))
+                        .arg(Arg::with_name("top")
+                            .long("top")
+                            .takes_value(true)
+                            .validator(is_number)
+                            .conflicts_with("unique")
+                            .help("only list the N biggest redundant files, each with the copy it duplicates, instead of the groups"))
+                        .arg(Arg::with_name("action")
+                            .long("action")
+                            .takes_value(true)
+                            .possible_values(options::ACTIONS)
+                            .conflicts_with_all(&["unique", "fuzzy-bytes", "ends", "tolerant-read"])
+                            .help("what to do with the duplicates: report them (default), reflink them to copy-on-write clones of the copy kept (Btrfs, XFS, APFS), symbolic links being left alone, or trash them to the desktop trash, recoverable from there"))
+                        .arg(Arg::with_name("keep")
+                            .long("keep")
+                            .takes_value(true)
+                            .possible_values(options::KEEPS)
+                            .help("copy of each group kept by --action and --top: the first one listed (default), the deepest or the shallowest path"))
+                        .arg(Arg::with_name("keep-in")
+                            .long("keep-in")
+                            .visible_alias("prefer-dir")
+                            .takes_value(true)
+                            .value_name("DIR")
+                            .multiple(true)
+                            .number_of_values(1)
+                            .help("keep a copy from under DIR whenever a group has one there, --keep choosing among the copies under it; repeatable, the first DIR holding a copy wins"))
+                        .arg(Arg::with_name("dry-run")
+                            .long("dry-run")
+                            .requires("action")
+                            .help("only print what --action would do"))
+                        .arg(Arg::with_name("lock")
+                            .long("lock")
+                            .takes_value(true)
+                            .value_name("PATH")
+                            .requires("action")
+                            .help("lock file held while acting on the files, instead of a .rustadup.lock in the root; a run finding it held refuses to start"))
+                        .arg(Arg::with_name("force-lock")
+                            .long("force-lock")
+                            .requires("action")
+                            .help("take over the lock even if held, after a crashed run left it behind"))
+                        .arg(Arg::with_name("include-risky")
+                            .long("include-risky")
+                            .requires("action")
+                            .help("also act on the targets otherwise left for review: under a system directory, read-only or recently modified"))
+                        .arg(Arg::with_name("risky-path")
+                            .long("risky-path")
+                            .takes_value(true)
+                            .value_name("PREFIX")
+                            .multiple(true)
+                            .number_of_values(1)
+                            .requires("action")
+                            .help("leave the targets under PREFIX for review, in place of the system directories (/bin, /etc, /usr, /var...); repeatable"))
+                        .arg(Arg::with_name("risky-age")
+                            .long("risky-age")
+                            .takes_value(true)
+                            .validator(is_duration)
+                            .requires("action")
+                            .help("leave the targets modified less than this long ago for review (default 1d, 0s for none)"))
+                        .arg(Arg::with_name("action-log")
+                            .long("action-log")
+                            .takes_value(true)
+                            .value_name("FILE")
+                            .requires("action")
+                            .conflicts_with("dry-run")
+                            .help("append every file reflinked or trashed to FILE, one JSON object per line (action, target, survivor, size, hash, timestamp)")))
+                    .subcommand(SubCommand::with_name("c")
+                        .about("Find files sharing parts of their content through content-defined chunks (experimental, reads and keeps in memory every chunk)")
+                        .arg(Arg::with_name("average-chunk")
+                            .long("average-chunk")
+                            .takes_value(true)
+                            .env("RUSTADUP_AVERAGE_CHUNK")
+                            .validator(size::is_size)
+                            .default_value("64K")
+                            .help("average chunk size, between 256 and 4M"))
+                        .arg(Arg::with_name("min-overlap")
+                            .long("min-overlap")
+                            .takes_value(true)
+                            .env("RUSTADUP_MIN_OVERLAP")
+                            .validator(is_percentage)
+                            .default_value("50")
+                            .help("minimum percentage of the smaller file found in the other one")))
+                    .subcommand(SubCommand::with_name("verify")
+                        .about("Check that the groups of a saved JSON report (--format json) still hold")
+                        .arg(Arg::with_name("REPORT")
+                            .required(true)
+                            .help("JSON report to check")))
+                    .subcommand(SubCommand::with_name("selftest")
+                        .about("Check the hashing on generated files of known content, e.g. after an upgrade or on a new platform"))
+                    .subcommand(SubCommand::with_name("diff")
+                        .about("Compare the contents of two directories: files in both, only in A and only in B (sha256, names ignored)")
+                        .arg(Arg::with_name("A")
+                            .required(true)
+                            .help("First directory"))
+                        .arg(Arg::with_name("B")
+                            .required(true)
+                            .help("Second directory")))
+                    .subcommand(SubCommand::with_name("merge")
+                        .about("Find duplicates across saved JSON reports of h scans (e.g. of drives never mounted together)")
+                        .arg(Arg::with_name("report")
+                            .short("r")
+                            .long("report")
+                            .takes_value(true)
+                            .required(true)
+                            .multiple(true)
+                            .number_of_values(1)
+                            .value_name("[LABEL=]PATH")
+                            .help("JSON report to merge (repeatable), labelled after its file name unless LABEL is given")))
+                    .arg(Arg::with_name("probe")
+                        .long("probe")
+                        .global(true)
+                        .help("Only print candidate counts for each comparison mode, without hashing"))
+                    .arg(Arg::with_name("nice")
+                        .long("nice")
+                        .global(true)
+                        .help("Run at the lowest CPU priority and the idle I/O class (Linux), so a background scan doesn't slow down other work"))
+                    .arg(Arg::with_name("type-check")
+                        .long("type-check")
+                        .global(true)
+                        .conflicts_with_all(&["probe", "count"])
+                        .help("Only list the files whose content, sniffed from its magic bytes, is of another type than their extension says (a .png holding a JPEG), without comparing"))
+                    .arg(Arg::with_name("quiet")
+                        .short("q")
+                        .long("quiet")
+                        .global(true)
+                        .help("Don't tell on stderr why nothing was found"))
+                    .arg(Arg::with_name("count")
+                        .long("count")
+                        .takes_value(true)
+                        .possible_values(COUNTS)
+                        .global(true)
+                        .conflicts_with("probe")
+                        .help("Only print one number on stdout: the files or bytes walked (no comparison done), or the groups, duplicate files or unique files of the comparison"))
+                    .arg(Arg::with_name("min-links")
+                        .long("min-links")
+                        .takes_value(true)
+                        .global(true)
+                        .validator(is_number)
+                        .help("Only consider files with at least this many hard links (ignored where unavailable)"))
+                    .arg(Arg::with_name("max-links")
+                        .long("max-links")
+                        .takes_value(true)
+                        .global(true)
+                        .validator(is_number)
+                        .help("Only consider files with at most this many hard links (ignored where unavailable)"))
+                    .arg(Arg::with_name("path-regex")
+                        .long("path-regex")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .global(true)
+                        .validator(is_regex)
+                        .help("Only consider files whose full path matches this regular expression (repeatable, any can match)"))
+                    .arg(Arg::with_name("path-regex-exclude")
+                        .long("path-regex-exclude")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .global(true)
+                        .validator(is_regex)
+                        .help("Skip files whose full path matches this regular expression (repeatable)"))
+                    .arg(Arg::with_name("mime")
+                        .long("mime")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("TYPE")
+                        .global(true)
+                        .help("Only consider files whose content type, sniffed from their first bytes, is TYPE (e.g. image/png or video, repeatable)"))
+                    .arg(Arg::with_name("skip-mime")
+                        .long("skip-mime")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("TYPE")
+                        .global(true)
+                        .help("Skip files whose content type, sniffed from their first bytes, is TYPE (repeatable)"))
+                    .arg(Arg::with_name("exclude-size")
+                        .long("exclude-size")
+                        .takes_value(true)
+                        .multiple(true)
+                        .use_delimiter(true)
+                        .value_name("SIZE")
+                        .validator(size::is_size)
+                        .global(true)
+                        .help("Skip files of exactly these sizes, comma-separated (e.g. 0,4K), before any hashing"))
+                    .arg(Arg::with_name("newer-than")
+                        .long("newer-than")
+                        .takes_value(true)
+                        .value_name("WHEN")
+                        .validator(is_time)
+                        .global(true)
+                        .help("Only consider files modified at or after WHEN: @path for the mtime of a reference file, a UTC date (2024-03-01) or a duration ago (2days)"))
+                    .arg(Arg::with_name("older-than")
+                        .long("older-than")
+                        .takes_value(true)
+                        .value_name("WHEN")
+                        .validator(is_time)
+                        .global(true)
+                        .help("Only consider files modified before WHEN, given as for --newer-than"))
+                    .arg(Arg::with_name("skip-marked")
+                        .long("skip-marked")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("MARKER")
+                        .validator(is_marker)
+                        .global(true)
+                        .help("Skip the files already handled, marked by an extended attribute (xattr:user.done) or a sibling file named after them (sibling:.done for photo.jpg.done), repeatable"))
+                    .arg(Arg::with_name("match-xattr")
+                        .long("match-xattr")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("NAME")
+                        .global(true)
+                        .help("Only group files whose extended attribute NAME (e.g. user.origin) is the same, repeatable"))
+                    .arg(Arg::with_name("exclude-group-if")
+                        .long("exclude-group-if")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .possible_values(options::GROUP_PREDICATES)
+                        .global(true)
+                        .help("Drop the groups whose members are all in the same directory, all hard links of one file, or empty (repeatable)"))
+                    .arg(Arg::with_name("cross-dir-only")
+                        .long("cross-dir-only")
+                        .global(true)
+                        .help("Only keep the groups with members in different directories, same as --exclude-group-if same-dir"))
+                    .arg(Arg::with_name("skip-network")
+                        .long("skip-network")
+                        .global(true)
+                        .help("Don't descend into network filesystems (NFS, SMB...), which are only warned about otherwise"))
+                    .arg(Arg::with_name("verbose")
+                        .long("verbose")
+                        .short("v")
+                        .global(true)
+                        .help("Tell on stderr about the files passed over because they vanished between the walk and their reading"))
+                    .arg(Arg::with_name("dump-candidates")
+                        .long("dump-candidates")
+                        .global(true)
+                        .help("Tell on stderr for every file found whether it is compared, or which filter left it out (troubleshooting)"))
+                    .arg(Arg::with_name("no-recursion")
+                        .long("no-recursion")
+                        .global(true)
+                        .help("Only compare the files right inside the directories given, not the ones of their subdirectories"))
+                    .arg(Arg::with_name("dereference-once")
+                        .long("dereference-once")
+                        .global(true)
+                        .help("Compare a symbolic link as the regular file it points to, leaving out the links to directories or to other links"))
+                    .arg(Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .env("RUSTADUP_FORMAT")
+                        .global(true)
+                        .possible_values(output::FORMATS)
+                        .default_value("text")
+                        .help("Output format of the duplicate groups"))
+                    .arg(Arg::with_name("template")
+                        .long("template")
+                        .takes_value(true)
+                        .global(true)
+                        .validator(is_template)
+                        .conflicts_with("split-output")
+                        .help("Print one line per member of the groups from this template instead of --format, with the placeholders {path}, {relpath}, {name}, {size}, {hash} and {group} (e.g. '{group} {size} {path}')"))
+                    .arg(Arg::with_name("max-files")
+                        .long("max-files")
+                        .takes_value(true)
+                        .global(true)
+                        .validator(is_number)
+                        .help("give up before comparing anything once more than N files were found, against a run started on the wrong directory"))
+                    .arg(Arg::with_name("timeout")
+                        .long("timeout")
+                        .takes_value(true)
+                        .env("RUSTADUP_TIMEOUT")
+                        .global(true)
+                        .validator(is_duration)
+                        .help("Stop scanning after this duration (e.g. 10m) and report what was found so far"))
+                    .arg(Arg::with_name("since-report")
+                        .long("since-report")
+                        .takes_value(true)
+                        .global(true)
+                        .value_name("FILE")
+                        .help("Only output the groups that are new or gained members since the JSON report FILE of the same comparison"))
+                    .arg(Arg::with_name("stats")
+                        .long("stats")
+                        .global(true)
+                        .help("Print statistics about the copies per group on stderr after the listing"))
+                    .arg(Arg::with_name("stats-json")
+                        .long("stats-json")
+                        .global(true)
+                        .help("Write a JSON summary of the run on stderr at the end (groups, redundant_files, reclaimable_bytes, files_scanned, errors, files_retried, elapsed_ms)"))
+                    .arg(Arg::with_name("relativize-to-root")
+                        .long("relativize-to-root")
+                        .global(true)
+                        .help("Print every member of the groups as its root then its path under it, flagging groups at the same relative path in every root (snapshots, backups)"))
+                    .arg(Arg::with_name("show-hash")
+                        .long("show-hash")
+                        .global(true)
+                        .help("Lead the text header of each group with its content hash, to check it against sha256sum"))
+                    .arg(Arg::with_name("show-mtime")
+                        .long("show-mtime")
+                        .global(true)
+                        .help("Show how long ago every member of the text output was modified (modified 3 days ago)"))
+                    .arg(Arg::with_name("iso-time")
+                        .long("iso-time")
+                        .global(true)
+                        .help("Show the modification time of every member of the text output as an ISO 8601 date instead"))
+                    .arg(Arg::with_name("split-output")
+                        .long("split-output")
+                        .takes_value(true)
+                        .global(true)
+                        .value_name("DIR")
+                        .help("Write every group to its own file in DIR, along with an index.tsv"))
                     .arg(Arg::with_name("DIRECTORY")
-                        .help("Root directory from which to search the files")
+                        .help("Root directories from which to search the files, compared together")
                         .global(true)
+                        .multiple(true)
                         .default_value("."));
                     
     let matches = app.get_matches();
-            
-
-    //Iterate over every file that can be seen and filter out the directories
-    let iter = WalkDir::new(matches.value_of("DIRECTORY").unwrap_or_default())
-            .into_iter()
-            .filter_map(Result::ok)
-            .filter(|e| !e.file_type().is_dir());
-    
+
+    if matches.is_present("nice") {
+        priority::lower()?;
+    }
+    find::set_verbose(matches.is_present("verbose"));
+
+    if let ("verify", Some(verify)) = matches.subcommand() {
+        return verify_report(verify.value_of("REPORT").unwrap_or_default());
+    }
+    if let ("selftest", Some(_)) = matches.subcommand() {
+        return self_test();
+    }
+    if let ("merge", Some(merge)) = matches.subcommand() {
+        let format = Format::from_name(matches.value_of("format").unwrap_or_default());
+        return merge_reports(merge.values_of("report").unwrap_or_default(), format, text_style(&matches));
+    }
+
+    let roots : Vec<PathBuf> = matches.values_of("DIRECTORY").into_iter().flatten().map(PathBuf::from).collect();
+    let mut options = Options {
+        filters: Filters {
+            min_links: value_t!(matches, "min-links", u64).ok(),
+            max_links: value_t!(matches, "max-links", u64).ok(),
+            path_regex: regex_set(matches.values_of("path-regex")),
+            path_regex_exclude: regex_set(matches.values_of("path-regex-exclude")),
+            skip_network: matches.is_present("skip-network"),
+            no_recursion: matches.is_present("no-recursion"),
+            dereference_once: matches.is_present("dereference-once"),
+            mime: matches.values_of("mime").map(|v| v.map(String::from).collect()).unwrap_or_default(),
+            skip_mime: matches.values_of("skip-mime").map(|v| v.map(String::from).collect()).unwrap_or_default(),
+            skip_marked: matches.values_of("skip-marked").into_iter().flatten().filter_map(Marker::from_spec).collect(),
+            newer_than: matches.value_of("newer-than").map(|t| options::parse_time(t).unwrap()),
+            older_than: matches.value_of("older-than").map(|t| options::parse_time(t).unwrap()),
+            exclude_sizes: matches.values_of("exclude-size").map(|v| v.map(|s| size::parse_size(s).unwrap()).collect()).unwrap_or_default(),
+        },
+        timeout: matches.value_of("timeout").map(|t| humantime::parse_duration(t).unwrap()),
+        relative_to_roots: matches.is_present("relativize-to-root"),
+        max_files: value_t!(matches, "max-files", u64).ok(),
+        dump_candidates: matches.is_present("dump-candidates"),
+        ..Options::default()
+    };
+
+    if let ("diff", Some(diff)) = matches.subcommand() {
+        let format = Format::from_name(matches.value_of("format").unwrap_or_default());
+        let a = Path::new(diff.value_of("A").unwrap_or_default());
+        let b = Path::new(diff.value_of("B").unwrap_or_default());
+        return print_diff(&diff::diff(&options, a, b)?, format, text_style(&matches));
+    }
+
+    //Walking alone tells the files and their bytes
+    if let Some(what @ ("files" | "bytes")) = matches.value_of("count") {
+        let deadline = Deadline::new(options.timeout);
+        let mut count = 0;
+        for (i, entry) in run::files(&options.filters, &roots, &deadline).enumerate() {
+            if options.max_files.is_some_and(|max| i as u64 >= max) {
+                exit_on_max_files(&options, &roots);
+            }
+            count += match find::unless_vanished(entry.path(), entry.metadata())? {
+                Some(metadata) if what == "bytes" => metadata.len(),
+                Some(_) => 1,
+                None => 0,
+            };
+        }
+        println!("{}", count);
+        return exit_on_timeout(deadline.expired());
+    }
+
+    if matches.is_present("type-check") {
+        let deadline = Deadline::new(options.timeout);
+        for entry in run::files(&options.filters, &roots, &deadline) {
+            if let Some(mismatch) = typecheck::check(&entry)? {
+                println!("{}: content is {} ({}), extension says {}", mismatch.path.to_string_lossy(), mismatch.detected, mismatch.mime, mismatch.declared);
+            }
+        }
+        return exit_on_timeout(deadline.expired());
+    }
+
+    if matches.is_present("probe") {
+        let deadline = Deadline::new(options.timeout);
+        probe(run::files(&options.filters, &roots, &deadline))?;
+        return exit_on_timeout(deadline.expired());
+    }
+
+    let mut top = None;
+    let mut lock_paths = Vec::new();
+    let mut force_lock = false;
     match matches.subcommand(){
-        ("n", Some(_)) => { file_names(iter) },
-        ("s", Some(_)) => { file_names_sizes(iter) },
+        ("n", Some(names)) => {
+            options.mode = if names.is_present("truncated") { Mode::Truncated } else { Mode::Names };
+            options.normalize_unicode = names.is_present("normalize-unicode");
+            options.two_pass = names.is_present("two-pass");
+        },
+        ("s", Some(sizes)) => {
+            options.mode = if sizes.is_present("confirm") { Mode::Confirmed } else { Mode::NamesSizes };
+            options.normalize_unicode = sizes.is_present("normalize-unicode");
+        },
         ("h", Some(hash)) => { 
-            file_hashes(iter, hash.is_present("big-files"), hash.is_present("small-files")) 
+            options.mode = match (value_t!(hash, "fuzzy-bytes", u64), hash.value_of("ends")) {
+                (Ok(max_diff), _) => Mode::FuzzyBytes(max_diff),
+                (_, Some(bytes)) => Mode::Ends(size::parse_size(bytes).unwrap()),
+                _ if hash.is_present("decompress") => Mode::Decompressed,
+                _ if hash.is_present("media-streams") => Mode::MediaStreams,
+                _ if hash.is_present("ignore-trailing-zeros") => Mode::Unpadded,
+                _ if hash.is_present("compare-cmd") => Mode::Command(String::from(hash.value_of("compare-cmd").unwrap_or_default())),
+                _ if hash.is_present("directories") => Mode::Directories,
+                _ if hash.is_present("match-hashes") => Mode::Matching(manifest::read_hashes(Path::new(hash.value_of("match-hashes").unwrap_or_default()))?),
+                _ if hash.is_present("range") => {
+                    let (start, len) = size::parse_range(hash.value_of("range").unwrap_or_default()).unwrap();
+                    Mode::Range { start, len }
+                },
+                _ if hash.is_present("skip-header") => Mode::SkipHeader(size::parse_size(hash.value_of("skip-header").unwrap_or_default()).unwrap()),
+                _ => Mode::Hashes,
+            };
+            options.hash = HashOptions {
+                skip_big: hash.is_present("big-files"),
+                skip_small: hash.is_present("small-files"),
+                unique: hash.is_present("unique"),
+                ignore_extension: hash.is_present("ignore-extension"),
+                tolerant_read: hash.is_present("tolerant-read"),
+                verify: hash.is_present("verify"),
+                hints: options.mode == Mode::Hashes && !hash.is_present("no-hints"),
+                retries: value_t!(hash, "retries", u32).unwrap_or(0),
+            };
+            top = value_t!(hash, "top", usize).ok();
+            options.ownership = if hash.is_present("ownership-differs") {
+                OwnershipCheck::Differing
+            } else if hash.is_present("ownership") {
+                OwnershipCheck::Annotate
+            } else {
+                OwnershipCheck::Ignore
+            };
+            options.index = hash.value_of("index").map(PathBuf::from);
+            options.memory_limit = hash.value_of("limit-memory").map(|l| size::parse_size(l).unwrap());
+            options.keep = Keep::from_name(hash.value_of("keep").unwrap_or("first"));
+            for dir in hash.values_of("keep-in").into_iter().flatten() {
+                let home = fs::canonicalize(dir).map_err(|e| Error::new(e.kind(), format!("--keep-in {}: {}", dir, e)))?;
+                options.keep_in.push(home);
+            }
+            options.checksum_output = hash.value_of("checksum-output").map(PathBuf::from);
+            options.cache_policy = CachePolicy::from_name(hash.value_of("cache-policy").unwrap_or("trust"));
+            options.max_read_rate = hash.value_of("max-read-rate").map(|r| size::parse_size(r).unwrap());
+            options.action = Action::from_name(hash.value_of("action").unwrap_or("report"), hash.is_present("dry-run"));
+            //`--action report` meets the requirement of the options below, it changes no file for them to apply to
+            if let (Action::Report, Some(option)) = (options.action, ACTION_OPTIONS.iter().find(|o| hash.is_present(o))) {
+                eprintln!("--{} needs an --action changing files", option);
+                std::process::exit(1);
+            }
+            if options.action != Action::Report {
+                options.action_log = hash.value_of("action-log").map(PathBuf::from);
+                let defaults = Risks::default();
+                options.risks = Risks {
+                    paths: hash.values_of("risky-path").map(|p| p.map(PathBuf::from).collect()).unwrap_or(defaults.paths),
+                    recent: hash.value_of("risky-age").map(|a| humantime::parse_duration(a).unwrap()).or(defaults.recent).filter(|a| !a.is_zero()),
+                };
+                options.include_risky = hash.is_present("include-risky");
+                lock_paths = match hash.value_of("lock") {
+                    Some(path) => vec![PathBuf::from(path)],
+                    None => roots.iter().map(|r| r.join(lock::LOCK_NAME)).collect(),
+                };
+                force_lock = hash.is_present("force-lock");
+            }
+        },
+        ("c", Some(chunks)) => {
+            options.mode = Mode::Chunks {
+                average: size::parse_size(chunks.value_of("average-chunk").unwrap_or_default()).unwrap(),
+                min_overlap: value_t!(chunks, "min-overlap", f64).unwrap(),
+            };
         },
         _ => { 
             eprintln!("Wrong subcommand specified");
             std::process::exit(1);
         },
-    }   
+    }
+
+    //Global arguments can't be declared in conflict with the ones of a subcommand
+    if top.is_some() && matches.is_present("split-output") {
+        eprintln!("--top lists files, not groups, and can't be used with --split-output");
+        std::process::exit(1);
+    }
+    let count = matches.value_of("count");
+    if count.is_some() && (top.is_some() || options.hash.unique || matches.is_present("split-output")) {
+        eprintln!("--count prints a single number and can't be used with --top, --unique or --split-output");
+        std::process::exit(1);
+    }
+    options.exclude_groups = matches.values_of("exclude-group-if").into_iter().flatten().filter_map(GroupPredicate::from_name).collect();
+    if matches.is_present("cross-dir-only") {
+        options.exclude_groups.push(GroupPredicate::SameDir);
+    }
+    options.match_xattr = matches.values_of("match-xattr").map(|v| v.map(String::from).collect()).unwrap_or_default();
+    if !options.match_xattr.is_empty() && !xattr::SUPPORTED_PLATFORM {
+        eprintln!("warning: extended attributes aren't supported on this platform, --match-xattr is ignored");
+    }
+    let baseline = match matches.value_of("since-report") {
+        Some(path) => {
+            let previous = read_report(path)?;
+            if previous.mode != options.mode.name() || previous.algorithm.as_deref() != options.mode.algorithm() {
+                return Err(Error::other(format!("{} comes from another comparison ({} {})", path, previous.mode, previous.algorithm.as_deref().unwrap_or("-"))));
+            }
+            Some(Baseline::new(&previous))
+        },
+        None => None,
+    };
+    let format = Format::from_name(matches.value_of("format").unwrap_or_default());
+    let style = text_style(&matches);
+    let acting = matches!(options.action, Action::Reflink { dry_run: false } | Action::Trash { dry_run: false });
+    let mut formatter : Box<dyn Formatter> = match (matches.value_of("split-output"), matches.value_of("template")) {
+        (Some(dir), _) => Box::new(SplitOutput::new(Path::new(dir), format, style)?),
+        (None, Some(template)) => Box::new(TemplateFormatter::new(io::stdout(), Template::parse(template).unwrap(), &roots)),
+        (None, None) => output::formatter(format, style, !acting),
+    };
+    //--count and --top print instead of the formatter, which is left unused
+    if count.is_none() && top.is_none() {
+        formatter.begin(options.mode.name(), options.mode.algorithm())?;
+    }
+    //Only --top needs every group before printing, formats needing them keep them themselves
+    let mut groups = Vec::new();
+    //Only an action changing files needs the lock, a dry run doesn't
+    let lock = if acting { Some(Lock::acquire(lock_paths, force_lock)?) } else { None };
+    let mut stats = GroupStats::default();
+    let mut report = run::run_streaming(&options, &roots, |group| {
+        if baseline.as_ref().is_some_and(|b| b.is_known(&group)) {
+            return Ok(());
+        }
+        stats.add(&group);
+        if count.is_some() {
+            return Ok(());
+        }
+        if top.is_some() {
+            groups.push(group);
+            return Ok(());
+        }
+        formatter.group(group)
+    })?;
+    drop(lock);
+    if report.capped {
+        //Closed as cut short, the exit skips the destructors
+        drop(formatter);
+        exit_on_max_files(&options, &roots);
+    }
+    if let Action::Reflink { dry_run } | Action::Trash { dry_run } = options.action {
+        print_steps(&report, options.action.name(), dry_run, options.include_risky);
+    }
+    if let (Mode::Ends(bytes), false) = (options.mode.clone(), options.hash.verify) {
+        eprintln!("groups are candidates only: files were compared on their first and last {} bytes", bytes);
+    }
+    if options.mode == Mode::MediaStreams {
+        eprintln!("groups are media matches: their files hold the same audio and video streams, not the same bytes");
+    }
+    if let Mode::Command(command) = &options.mode {
+        eprintln!("groups are matches of {}: their files give the same output, not necessarily the same bytes", command);
+    }
+    if matches.is_present("stats") {
+        print_stats(&stats);
+    }
+    if report.stats.files_retried > 0 && !matches.is_present("quiet") {
+        eprintln!("{} files needed retries to be read", report.stats.files_retried);
+    }
+    if matches.is_present("stats-json") {
+        eprintln!("{}", serde_json::to_string(&report.summary()).map_err(Error::other)?);
+    }
+    report.groups = groups;
+    let timed_out = report.timed_out;
+    match (count, top) {
+        (Some("groups"), _) => println!("{}", stats.groups()),
+        (Some("duplicates"), _) => println!("{}", stats.files()),
+        //Every file compared is either in a group or unique
+        (Some(_), _) => println!("{}", report.stats.files_scanned.saturating_sub(stats.files() as u64)),
+        (None, Some(n)) => print_top(&report.groups, n, options.keep, &options.keep_in)?,
+        (None, None) => formatter.finish()?,
+    }
+    exit_on_timeout(timed_out)?;
+    if stats.groups() == 0 {
+        if !matches.is_present("quiet") {
+            match report.stats.files_scanned {
+                //Rather a wrong path or filters than a clean tree
+                0 => eprintln!("no files matched under {}", roots.iter().map(|r| r.to_string_lossy()).collect::<Vec<_>>().join(", ")),
+                scanned => eprintln!("{} files scanned, no {} found", scanned, match options.mode {
+                    Mode::Matching(_) => "file with a listed hash",
+                    _ if options.hash.unique => "unique files",
+                    _ => "duplicates",
+                }),
+            }
+        }
+        std::process::exit(EXIT_NO_GROUPS);
+    }
+    Ok(())
 }
 
-fn file_names<'a, I>(iter: I) -> Result<(), Error> 
-where I: IntoIterator<Item= DirEntry>,
-{
-    let mut filenames : HashMap<String, Vec<DirEntry>> = HashMap::new();
-    for entry in iter.into_iter() {
-        let f_name = String::from(entry.file_name().to_string_lossy());
-        let counter = filenames.entry(f_name).or_insert(Vec::new());
-        counter.push(entry);
+fn print_steps(report: &Report, action: &str, dry_run: bool, include_risky: bool) {
+    //Without --include-risky the targets for review were left alone
+    let (review, acted) : (Vec<ActionStep>, Vec<ActionStep>) = report.steps.iter().cloned().partition(|s| s.risk.is_some() && !include_risky);
+    let savings = Savings::of(&acted);
+    if dry_run {
+        for step in acted.iter() {
+            let risk = step.risk.as_ref().map(|r| format!(", {}", r)).unwrap_or_default();
+            match action {
+                "trash" => eprintln!("trash {} (copy of {}{})", step.target.to_string_lossy(), step.survivor.to_string_lossy(), risk),
+                _ => eprintln!("{} {} -> {}{}", action, step.target.to_string_lossy(), step.survivor.to_string_lossy(), risk),
+            }
+        }
+        eprintln!("duplicated content: {} now, {} after, {} reclaimed",
+            size::format_size(savings.before), size::format_size(savings.after), size::format_size(savings.reclaimed()));
     }
-    for files in filenames.into_iter().filter(|e| e.1.len() != 1) {
-        println!("{filename}:",filename=files.0);
-        for f in files.1.into_iter() {
-            println!("\t{filepath}",filepath=f.path().to_string_lossy());
+    for step in review.iter() {
+        eprintln!("review {} (copy of {}, {})", step.target.to_string_lossy(), step.survivor.to_string_lossy(), step.risk.as_deref().unwrap_or_default());
+    }
+    if !review.is_empty() {
+        eprintln!("{} files left for review, --include-risky to {} them too", review.len(), action);
+    }
+    let done = if dry_run { format!("would {}", action) } else { format!("{}ed", action) };
+    eprintln!("{} {} files, {} bytes reclaimed", done, acted.len(), savings.reclaimed());
+}
+
+fn print_stats(stats: &GroupStats) {
+    eprintln!("groups\t{}", stats.groups());
+    eprintln!("files\t{}", stats.files());
+    if let Some(median) = stats.median() {
+        eprintln!("median copies\t{}", median);
+    }
+    if let Some((name, count)) = stats.largest() {
+        eprintln!("max copies\t{} ({})", count, name);
+    }
+}
+
+fn print_diff(diff: &TreeDiff, format: Format, style: TextStyle) -> Result<(), Error> {
+    if let Format::Json | Format::JsonStream | Format::JsonArray = format {
+        println!("{}", serde_json::to_string_pretty(diff).map_err(Error::other)?);
+    } else if format == Format::Fclones {
+        fclones::write_report(&mut io::stdout(), &diff.both)?;
+    } else {
+        for (title, files) in [("only in A", &diff.only_a), ("only in B", &diff.only_b)] {
+            println!("{}:", title);
+            for f in files.iter() {
+                println!("\t{}", f.to_string_lossy());
+            }
+        }
+        println!("in both:");
+        for group in diff.both.iter() {
+            output::print_text_group(group, style)?;
         }
     }
+    exit_on_timeout(diff.timed_out)
+}
+
+fn print_top(groups: &[DuplicateGroup], n: usize, keep: Keep, homes: &[PathBuf]) -> Result<(), Error> {
+    for step in report::top_files(groups, n, keep, homes)?.into_iter() {
+        println!("{}\t{} (copy of {})", size::format_size(step.size), step.target.to_string_lossy(), step.survivor.to_string_lossy());
+    }
     Ok(())
 }
 
-fn file_names_sizes<'a, I>(iter: I) -> Result<(), Error> 
-where I: IntoIterator<Item= DirEntry>,
+fn read_report(path: &str) -> Result<Document, Error> {
+    let reader = decompress::open_report(Path::new(path))?;
+    let document : Document = serde_json::from_reader(reader).map_err(Error::other)?;
+    if document.version > output::FORMAT_VERSION {
+        return Err(Error::other(format!("report version {} is newer than supported version {}", document.version, output::FORMAT_VERSION)));
+    }
+    Ok(document)
+}
+
+fn verify_report(path: &str) -> Result<(), Error> {
+    let document = read_report(path)?;
+    let mut throttle = Throttle::new(None);
+    let mut failed = 0;
+    for group in document.groups.iter() {
+        let mismatches = verify::verify_group(&document, group, &mut throttle)?;
+        if mismatches.is_empty() {
+            continue
+        }
+        failed += 1;
+        println!("{filename}:",filename=group.name);
+        for (f, mismatch) in mismatches.into_iter() {
+            let reason = match mismatch {
+                Mismatch::Missing => String::from("missing"),
+                Mismatch::Size(size) => format!("size is now {}", size),
+                Mismatch::Hash(hash) => format!("hash is now {}", hash),
+            };
+            println!("\t{filepath}: {reason}",filepath=f.to_string_lossy(),reason=reason);
+        }
+    }
+    eprintln!("{} of {} groups no longer hold", failed, document.groups.len());
+    if failed != 0 {
+        std::process::exit(EXIT_VERIFY_FAILED);
+    }
+    Ok(())
+}
+
+fn self_test() -> Result<(), Error> {
+    let checks = selftest::run()?;
+    let failed = checks.iter().filter(|c| !c.passed()).count();
+    for check in checks.iter().filter(|c| !c.passed()) {
+        println!("{}: got {}, expected {}", check.name, check.actual, check.expected);
+    }
+    eprintln!("{} of {} checks failed", failed, checks.len());
+    if failed != 0 {
+        std::process::exit(EXIT_SELFTEST_FAILED);
+    }
+    Ok(())
+}
+
+fn merge_reports<'a, I>(arguments: I, format: Format, style: TextStyle) -> Result<(), Error>
+where I: Iterator<Item= &'a str>,
 {
-    let mut filenames : HashMap<(String,u64), Vec<DirEntry>> = HashMap::new();
-    for entry in iter.into_iter() {
-        let f_name = String::from(entry.file_name().to_string_lossy());
-        let f_size = entry.metadata()?.len();
-        let counter = filenames.entry((f_name,f_size)).or_insert(Vec::new());
-        counter.push(entry);
-    }
-    for files in filenames.into_iter().filter(|e| e.1.len() != 1) {
-        println!("{filename}:",filename=files.0.0);
-        for f in files.1.into_iter() {
-            println!("\t{filepath}",filepath=f.path().to_string_lossy());
+    let mut reports = Vec::new();
+    for argument in arguments {
+        let (label, path) = match argument.split_once('=') {
+            Some((label, path)) => (String::from(label), path),
+            None => {
+                let label = Path::new(argument).file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+                (label, argument)
+            },
+        };
+        let document = read_report(path)?;
+        if document.algorithm.as_deref() != Some("sha256") {
+            eprintln!("{} has no sha256 hashes, it was left out", path);
+            continue
         }
+        reports.push((label, document));
+    }
+    let document = Document::new("h", Some("sha256"), merge::merge(&reports));
+    output::print_groups(document, format, style)
+}
+
+fn text_style(matches: &ArgMatches) -> TextStyle {
+    let mtime = if matches.is_present("iso-time") {
+        Some(TimeFormat::Iso)
+    } else if matches.is_present("show-mtime") {
+        Some(TimeFormat::Relative)
+    } else {
+        None
+    };
+    TextStyle { show_hash: matches.is_present("show-hash"), mtime }
+}
+
+fn exit_on_max_files(options: &Options, roots: &[PathBuf]) -> ! {
+    let roots : Vec<_> = roots.iter().map(|r| r.to_string_lossy()).collect();
+    eprintln!("more than {} files under {}, stopped by --max-files before comparing them", options.max_files.unwrap_or_default(), roots.join(", "));
+    std::process::exit(EXIT_MAX_FILES);
+}
+
+fn exit_on_timeout(timed_out: bool) -> Result<(), Error> {
+    if timed_out {
+        eprintln!("timed out, results partial");
+        std::process::exit(EXIT_TIMEOUT);
     }
     Ok(())
 }
 
-fn file_hashes<'a, I>(iter: I, bigfile: bool, smallfile: bool) -> Result<(), Error> 
+fn is_time(value: String) -> Result<(), String> {
+    options::parse_time(&value).map(|_| ())
+}
+
+fn is_duration(value: String) -> Result<(), String> {
+    humantime::parse_duration(&value).map(|_| ()).map_err(|e| e.to_string())
+}
+
+fn normalize_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("normalize-unicode")
+        .long("normalize-unicode")
+        .help("compare names in Unicode NFC form so differently composed names match")
+}
+
+fn is_percentage(value: String) -> Result<(), String> {
+    match value.parse::<f64>() {
+        Ok(p) if (0.0..=100.0).contains(&p) => Ok(()),
+        _ => Err(String::from("expected a percentage between 0 and 100")),
+    }
+}
+
+fn is_regex(value: String) -> Result<(), String> {
+    Regex::new(&value).map(|_| ()).map_err(|e| e.to_string())
+}
+
+fn is_template(value: String) -> Result<(), String> {
+    Template::parse(&value).map(|_| ())
+}
+
+fn regex_set(values: Option<clap::Values>) -> Option<RegexSet> {
+    values.map(|v| RegexSet::new(v).unwrap())
+}
+
+fn is_number(value: String) -> Result<(), String> {
+    value.parse::<u64>().map(|_| ()).map_err(|e| e.to_string())
+}
+
+fn is_marker(value: String) -> Result<(), String> {
+    match Marker::from_spec(&value) {
+        Some(_) => Ok(()),
+        None => Err(String::from("expected xattr:NAME or sibling:SUFFIX")),
+    }
+}
+
+/*
+ * Cheap overview of the duplication in the tree: only names and sizes are looked at,
+ * so the size buckets are an upper bound of what a content comparison could find
+ */
+fn probe<I>(iter: I) -> Result<(), Error>
 where I: IntoIterator<Item= DirEntry>,
 {
-    let mut filenames : HashMap<(String,u64, GenericArray<u8, <sha2::Sha256 as Digest>::OutputSize>), Vec<DirEntry>> = HashMap::new();
+    let mut names : HashMap<String, u64> = HashMap::new();
+    let mut sizes : HashMap<u64, u64> = HashMap::new();
+    let mut names_sizes : HashMap<(String,u64), u64> = HashMap::new();
+    let mut total = 0;
     for entry in iter.into_iter() {
         let f_name = String::from(entry.file_name().to_string_lossy());
-        let f_size = entry.metadata()?.len();
-        if (bigfile && f_size > BIG_FILE_SIZE) || (smallfile && f_size < SMALL_FILE_SIZE) {
-            continue
-        }
-        let file = fs::File::open(entry.path())?;
-        let mut reader = BufReader::new(file);
-        let f_hash = process::<Sha256,_>(&mut reader)?;
-        let counter = filenames.entry((f_name,f_size, f_hash)).or_insert(Vec::new());
-        counter.push(entry);
-    }
-    for files in filenames.into_iter().filter(|e| e.1.len() != 1) {
-        println!("{filename}:",filename=files.0.0);
-        for f in files.1.into_iter() {
-            println!("\t{filepath}",filepath=f.path().to_string_lossy());
-        }
+        let f_size = match find::unless_vanished(entry.path(), entry.metadata())? {
+            Some(metadata) => metadata.len(),
+            None => continue,
+        };
+        *names.entry(f_name.clone()).or_default() += 1;
+        *sizes.entry(f_size).or_default() += 1;
+        *names_sizes.entry((f_name,f_size)).or_default() += 1;
+        total += 1;
     }
+    //Bytes that the h subcommand would have to read (it groups by name and size first)
+    let to_hash : u64 = names_sizes.iter().filter(|e| *e.1 != 1).map(|e| e.0.1 * e.1).sum();
+
+    println!("{:<24}{:>12}{:>12}", "level", "groups", "files");
+    println!("{:<24}{:>12}{:>12}", "scanned", "-", total);
+    print_probe_row("names (n)", names.values());
+    print_probe_row("names + sizes (s)", names_sizes.values());
+    print_probe_row("sizes (content bound)", sizes.values());
+    println!("bytes to hash with h: {}", to_hash);
     Ok(())
 }
 
-// provided by https://github.com/RustCrypto/hashes/blob/master/sha2/examples/sha256sum.rs
-fn process<D: Digest + Default, R: Read>(reader: &mut R) -> Result<GenericArray<u8, <D as Digest>::OutputSize>, Error>{
-    let mut sh = D::default();
-    let mut buffer = [0u8; BUFFER_SIZE];
-    loop {
-        let n = reader.read(&mut buffer)?;
-        sh.update(&buffer[..n]);
-        if n == 0 || n < BUFFER_SIZE {
-            break;
-        }
-    }
-    Ok(sh.finalize())
+fn print_probe_row<'a, I>(level: &str, counts: I)
+where I: Iterator<Item= &'a u64>,
+{
+    let (groups, files) = counts.filter(|c| **c != 1).fold((0, 0), |acc, c| (acc.0 + 1, acc.1 + c));
+    println!("{:<24}{:>12}{:>12}", level, groups, files);
 }
@@ -0,0 +1,115 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+
+/*
+ * Checksum file in the format of sha256sum, one `<hex digest>  <path>` line per file,
+ * which `sha256sum -c` can check later.
+ * The two spaces are the text mode marker sha256sum prints by default; it reads files as
+ * bytes either way on unix, so binary mode (a `*` in place of the second space) would
+ * name the same hash. Like sha256sum, a path holding a backslash or a newline is escaped
+ * and its line starts with a backslash.
+ */
+pub struct Manifest {
+    out: BufWriter<fs::File>,
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+//The line of a file, without its end
+pub fn line(path: &Path, hash: &str) -> Vec<u8> {
+    let name = path_bytes(path);
+    let escaped = name.iter().any(|&b| b == b'\\' || b == b'\n' || b == b'\r');
+    let mut line = Vec::with_capacity(name.len() + 68);
+    if escaped {
+        line.push(b'\\');
+    }
+    line.extend(format!("{}  ", hash).into_bytes());
+    for &b in name.iter() {
+        match b {
+            b'\\' => line.extend(b"\\\\"),
+            b'\n' => line.extend(b"\\n"),
+            b'\r' => line.extend(b"\\r"),
+            _ => line.push(b),
+        }
+    }
+    line
+}
+
+impl Manifest {
+    pub fn create(path: &Path) -> Result<Manifest, Error> {
+        Ok(Manifest { out: BufWriter::new(fs::File::create(path)?) })
+    }
+
+    pub fn record(&mut self, path: &Path, hash: &str) -> Result<(), Error> {
+        self.out.write_all(&line(path, hash))?;
+        writeln!(self.out)
+    }
+
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.out.flush()
+    }
+}
+
+/*
+ * Sha256 digests listed in a file, one per line: either the digest alone or a line of
+ * sha256sum, whose path is then ignored. Blank lines and lines starting with # are
+ * skipped. Anything else than 64 hex digits is refused, a digest of another algorithm
+ * (md5, sha1) could never match.
+ */
+pub fn read_hashes(path: &Path) -> Result<HashSet<String>, Error> {
+    let mut hashes = HashSet::new();
+    for (i, line) in BufReader::new(fs::File::open(path)?).lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue
+        }
+        let digest = line.trim_start_matches('\\').split_whitespace().next().unwrap_or_default();
+        if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("{}:{}: {} isn't a sha256 digest", path.to_string_lossy(), i + 1, digest)));
+        }
+        hashes.insert(digest.to_lowercase());
+    }
+    Ok(hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn plain_line() {
+        assert_eq!(line(Path::new("dir/a b"), HASH), format!("{}  dir/a b", HASH).into_bytes());
+    }
+
+    #[test]
+    fn escaped_line() {
+        assert_eq!(line(Path::new("a\\b\nc\rd"), HASH), format!("\\{}  a\\\\b\\nc\\rd", HASH).into_bytes());
+    }
+
+    #[test]
+    fn hashes_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("SHA256SUMS");
+        let mut manifest = Manifest::create(&path).unwrap();
+        manifest.record(Path::new("a\nb"), HASH).unwrap();
+        manifest.finish().unwrap();
+        fs::write(dir.path().join("list"), format!("# known\n\n{}\n", HASH.to_uppercase())).unwrap();
+        assert_eq!(read_hashes(&path).unwrap(), read_hashes(&dir.path().join("list")).unwrap());
+        fs::write(dir.path().join("md5"), "d41d8cd98f00b204e9800998ecf8427e  a\n").unwrap();
+        assert_eq!(read_hashes(&dir.path().join("md5")).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
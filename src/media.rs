@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use sha2::{Digest, Sha256};
+use walkdir::DirEntry;
+use crate::deadline::Deadline;
+use crate::find::{self, HashOptions};
+use crate::group::DuplicateGroup;
+use crate::hash::Hash;
+use crate::throttle::Throttle;
+
+//Last extensions of the audio and video files handed to ffmpeg, the others are left out
+pub const MEDIA_EXTENSIONS: &[&str] = &[
+    "avi", "flac", "flv", "m2ts", "m4a", "m4v", "mka", "mkv", "mov", "mp3", "mp4",
+    "mpeg", "mpg", "oga", "ogg", "ogv", "opus", "ts", "webm", "wma", "wmv",
+];
+
+fn is_media(name: &str) -> bool {
+    find::extension(name).is_some_and(|e| MEDIA_EXTENSIONS.contains(&e.rsplit('.').next().unwrap_or_default()))
+}
+
+//Whether ffmpeg can be run, checked once before hashing anything
+fn ffmpeg_available() -> bool {
+    Command::new("ffmpeg").arg("-version").stdout(Stdio::null()).stderr(Stdio::null()).status().is_ok_and(|s| s.success())
+}
+
+/*
+ * Sha256 of the audio and video streams of the file as ffmpeg demuxes them, whatever
+ * the container: the packets are copied, not decoded, and hashed stream by stream by
+ * the streamhash muxer. Subtitles, attachments and metadata are left out.
+ * None when ffmpeg can't read the file, which is then warned about.
+ */
+pub fn stream_hash(path: &Path) -> Result<Option<Hash>, Error> {
+    let output = Command::new("ffmpeg")
+        .args(["-v", "error", "-nostdin", "-i"])
+        .arg(path)
+        .args(["-map", "0:v?", "-map", "0:a?", "-c", "copy", "-f", "streamhash", "-hash", "sha256", "-"])
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() || output.stdout.is_empty() {
+        let reason = String::from_utf8_lossy(&output.stderr);
+        eprintln!("warning: ffmpeg can't read the streams of {}: {}", path.to_string_lossy(), reason.trim());
+        return Ok(None);
+    }
+    Ok(Some(Sha256::digest(&output.stdout)))
+}
+
+pub fn find_by_streams<I>(iter: I, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline) -> Result<Vec<DuplicateGroup>, Error>
+where I: IntoIterator<Item= DirEntry>,
+{
+    find::collect(|emit| for_each_by_streams(iter, options, throttle, deadline, emit))
+}
+
+/*
+ * Groups of media files holding the same streams in different containers, such as
+ * `film.mkv` and `film.mp4`: the key is the name without its extensions and the stream
+ * hash, sizes differ from one container to the other and aren't compared.
+ * These are media matches, not identical files. ffmpeg has to be on the PATH, its reads
+ * are accounted in the throttle once each file is done.
+ */
+pub fn for_each_by_streams<I, F>(iter: I, options: HashOptions, throttle: &mut Throttle, deadline: &Deadline, mut emit: F) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    if !ffmpeg_available() {
+        return Err(Error::new(ErrorKind::NotFound, "--media-streams needs ffmpeg, which isn't on the PATH"));
+    }
+    let mut stems : HashMap<String, Vec<(DirEntry, u64)>> = HashMap::new();
+    for entry in iter.into_iter() {
+        let name = find::key_name(&entry, false);
+        if !is_media(&name) {
+            continue
+        }
+        let f_size = match find::unless_vanished(entry.path(), entry.metadata())? {
+            Some(metadata) => metadata.len(),
+            None => continue,
+        };
+        if options.skips(f_size) {
+            continue
+        }
+        stems.entry(String::from(find::strip_extensions(&name))).or_default().push((entry, f_size));
+    }
+    for (stem, files) in stems.into_iter() {
+        if files.len() < 2 && !options.unique {
+            continue
+        }
+        let mut hashes : HashMap<Hash, Vec<PathBuf>> = HashMap::new();
+        let mut complete = true;
+        for (entry, f_size) in files.into_iter() {
+            if deadline.expired() {
+                complete = false;
+                break
+            }
+            if let Some(f_hash) = stream_hash(entry.path())? {
+                hashes.entry(f_hash).or_default().push(entry.into_path());
+            }
+            throttle.consume(f_size as usize);
+        }
+        if complete || !options.unique {
+            for (f_hash, files) in hashes.into_iter().filter(|e| (e.1.len() == 1) == options.unique) {
+                emit(DuplicateGroup::new(stem.clone(), None, Some(format!("{:x}", f_hash)), files))?;
+            }
+        }
+        if !complete {
+            break
+        }
+    }
+    Ok(())
+}
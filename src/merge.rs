@@ -0,0 +1,42 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use crate::group::DuplicateGroup;
+use crate::output::Document;
+
+/*
+ * Duplicates across saved reports of separate scans (e.g. drives that are never
+ * mounted at the same time), each report being tagged with a label.
+ * Every file of a report with both a size and a sha256 hash is an entry, whatever its
+ * group was: the entries are regrouped on (size, hash) alone and only the groups
+ * spanning more than one label are kept.
+ * A file is only seen if its report listed it, a scan meant for merging should save
+ * both its duplicates (h) and its unique files (h -u).
+ */
+pub fn merge(reports: &[(String, Document)]) -> Vec<DuplicateGroup> {
+    let mut contents : HashMap<(u64, String), BTreeSet<(String, PathBuf)>> = HashMap::new();
+    for (label, document) in reports.iter() {
+        if document.algorithm.as_deref() != Some("sha256") {
+            continue
+        }
+        //A suspect hash tells nothing certain about the content
+        for group in document.groups.iter().filter(|g| !g.suspect) {
+            if let (Some(size), Some(hash)) = (group.size, group.hash.as_ref()) {
+                let entries = contents.entry((size, hash.clone())).or_default();
+                entries.extend(group.files.iter().map(|f| (label.clone(), f.clone())));
+            }
+        }
+    }
+    let mut groups = Vec::new();
+    for ((size, hash), entries) in contents.into_iter() {
+        let labels : BTreeSet<&String> = entries.iter().map(|e| &e.0).collect();
+        if labels.len() < 2 {
+            continue
+        }
+        let (sources, files) : (Vec<String>, Vec<PathBuf>) = entries.into_iter().unzip();
+        let name = files[0].file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let mut group = DuplicateGroup::new(name, Some(size), Some(hash), files);
+        group.sources = Some(sources);
+        groups.push(group);
+    }
+    groups
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::io::Error;
+use std::path::Path;
+use walkdir::DirEntry;
+use crate::find;
+
+/*
+ * Detection of network filesystems, so a walk doesn't silently hash gigabytes over
+ * an SMB or NFS share mounted somewhere under the roots.
+ * Only directories are looked at: a file is always on the filesystem of its parent.
+ */
+pub struct NetworkGuard {
+    //leave network directories out of the walk instead of warning about them
+    skip: bool,
+    //answer per device, each filesystem is only asked once
+    known: HashMap<u64, bool>,
+}
+
+impl NetworkGuard {
+    pub fn new(skip: bool) -> NetworkGuard {
+        NetworkGuard {
+            skip,
+            known: HashMap::new(),
+        }
+    }
+
+    //Whether the walk goes on into the entry, warns once per network filesystem met
+    pub fn keeps(&mut self, entry: &DirEntry) -> bool {
+        if !find::is_directory(entry) {
+            return true;
+        }
+        let network = match device(entry) {
+            Some(dev) => match self.known.get(&dev) {
+                Some(network) => return !(*network && self.skip),
+                None => {
+                    let network = is_network(entry.path()).unwrap_or(false);
+                    self.known.insert(dev, network);
+                    network
+                },
+            },
+            None => is_network(entry.path()).unwrap_or(false),
+        };
+        if network && !self.skip {
+            eprintln!("warning: {} is on a network filesystem, use --skip-network to leave it out", entry.path().to_string_lossy());
+        }
+        !(network && self.skip)
+    }
+}
+
+#[cfg(unix)]
+fn device(entry: &DirEntry) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    entry.metadata().ok().map(|m| m.dev())
+}
+
+#[cfg(not(unix))]
+fn device(_entry: &DirEntry) -> Option<u64> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+pub fn is_network(path: &Path) -> Result<bool, Error> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    //Magic numbers from linux/magic.h and the filesystems' own sources
+    const NETWORK: &[u32] = &[
+        0x6969,     // nfs
+        0x517B,     // smb
+        0xFF534D42, // cifs
+        0xFE534D42, // smb2
+        0x73757245, // coda
+        0x5346414F, // afs
+        0x6B414653, // kafs
+        0x01021997, // 9p
+        0x00C36400, // ceph
+        0x47504653, // gpfs
+        0x0BD00BD0, // lustre
+    ];
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut buffer: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut buffer) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    Ok(NETWORK.contains(&(buffer.f_type as u32)))
+}
+
+#[cfg(target_os = "macos")]
+pub fn is_network(path: &Path) -> Result<bool, Error> {
+    use std::ffi::{CStr, CString};
+    use std::os::unix::ffi::OsStrExt;
+    const NETWORK: &[&str] = &["nfs", "smbfs", "afpfs", "webdav", "cifs", "ftp"];
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut buffer: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut buffer) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    let name = unsafe { CStr::from_ptr(buffer.f_fstypename.as_ptr()) };
+    Ok(NETWORK.contains(&name.to_string_lossy().as_ref()))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn is_network(_path: &Path) -> Result<bool, Error> {
+    Ok(false)
+}
@@ -0,0 +1,468 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use regex::RegexSet;
+use walkdir::DirEntry;
+use crate::action::Risks;
+use crate::find::HashOptions;
+use crate::group::DuplicateGroup;
+
+//Way files are compared, one per subcommand of the command line
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mode {
+    //name only (n)
+    Names,
+    //name and size (s)
+    NamesSizes,
+    //name and size, then sha256 within these groups only (s --confirm)
+    Confirmed,
+    //name, size and sha256 (h)
+    Hashes,
+    //name and size, then contents differing in at most this many bytes (h --fuzzy-bytes)
+    FuzzyBytes(u64),
+    //name, size and sha256 of the first and last this many bytes, candidates only (h --ends)
+    Ends(u64),
+    //name without compression extension, size and sha256 of the decompressed content (h --decompress)
+    Decompressed,
+    //name, size and sha256 of the content after this many bytes (h --skip-header)
+    SkipHeader(u64),
+    //name, size and sha256 of this byte range of the content (h --range)
+    Range { start: u64, len: u64 },
+    //name without extensions and sha256 of the audio and video streams, media matches only (h --media-streams)
+    MediaStreams,
+    //name and sha256 of the content without its trailing zeros, sizes differ (h --ignore-trailing-zeros)
+    Unpadded,
+    //name and sha256 of the output of this command run on each file, sizes differ (h --compare-cmd)
+    Command(String),
+    //name, size and sha256 of the files, and the directories holding the same trees (h --directories)
+    Directories,
+    //sha256 among these hex digests, every file hashed and a lone one reported too (h --match-hashes)
+    Matching(HashSet<String>),
+    //same name, content prefix of another one (n --truncated)
+    Truncated,
+    //shared content-defined chunks (c)
+    Chunks { average: u64, min_overlap: f64 },
+}
+
+impl Mode {
+    //Subcommand name, as found in the JSON documents
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mode::Names | Mode::Truncated => "n",
+            Mode::NamesSizes | Mode::Confirmed => "s",
+            Mode::Hashes | Mode::FuzzyBytes(_) | Mode::Ends(_) | Mode::Decompressed | Mode::SkipHeader(_) | Mode::Range { .. } | Mode::MediaStreams | Mode::Unpadded | Mode::Command(_) | Mode::Directories | Mode::Matching(_) => "h",
+            Mode::Chunks { .. } => "c",
+        }
+    }
+
+    pub fn algorithm(&self) -> Option<&'static str> {
+        match self {
+            Mode::Names | Mode::NamesSizes => None,
+            Mode::Hashes | Mode::Confirmed => Some("sha256"),
+            Mode::FuzzyBytes(_) => Some("fuzzy-bytes"),
+            Mode::Ends(_) => Some("sha256-ends"),
+            Mode::Decompressed => Some("sha256-decompressed"),
+            Mode::SkipHeader(_) => Some("sha256-skip-header"),
+            Mode::Range { .. } => Some("sha256-range"),
+            Mode::MediaStreams => Some("sha256-media-streams"),
+            Mode::Unpadded => Some("sha256-unpadded"),
+            Mode::Command(_) => Some("sha256-command"),
+            Mode::Directories => Some("sha256-directories"),
+            Mode::Matching(_) => Some("sha256"),
+            Mode::Truncated => Some("sha256-prefix"),
+            Mode::Chunks { .. } => Some("fastcdc-sha256"),
+        }
+    }
+}
+
+pub const ACTIONS: &[&str] = &["report", "reflink", "trash"];
+
+//What is done with the duplicate groups once found
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Report,
+    Reflink { dry_run: bool },
+    //move the targets to the platform trash, recoverable from there
+    Trash { dry_run: bool },
+}
+
+impl Action {
+    pub fn from_name(name: &str, dry_run: bool) -> Action {
+        match name {
+            "reflink" => Action::Reflink { dry_run },
+            "trash" => Action::Trash { dry_run },
+            _ => Action::Report,
+        }
+    }
+
+    //As found in the action log
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Report => "report",
+            Action::Reflink { .. } => "reflink",
+            Action::Trash { .. } => "trash",
+        }
+    }
+}
+
+//Use of the mode and owner of the members of each group
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OwnershipCheck {
+    Ignore,
+    //list them along the members
+    Annotate,
+    //list them, and only report the groups where they differ
+    Differing,
+}
+
+pub const KEEPS: &[&str] = &["first", "deepest", "shallowest"];
+
+//Copy of a group kept by an action, among its regular files (see action::plan)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Keep {
+    //the first one listed
+    First,
+    //the one with the most path components, a copy filed away rather than one left lying around
+    Deepest,
+    //the one with the fewest path components
+    Shallowest,
+}
+
+impl Keep {
+    pub fn from_name(name: &str) -> Keep {
+        match name {
+            "deepest" => Keep::Deepest,
+            "shallowest" => Keep::Shallowest,
+            _ => Keep::First,
+        }
+    }
+}
+
+pub const CACHE_POLICIES: &[&str] = &["trust", "verify", "ignore"];
+
+//Use of the hashes an index kept from the earlier runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CachePolicy {
+    //reuse the hash of a file whose size and mtime didn't change
+    Trust,
+    //same, after rehashing a sample of them to catch contents changed behind an unchanged mtime
+    Verify,
+    //reuse the hashes but leave the database as it was
+    Ignore,
+}
+
+impl CachePolicy {
+    pub fn from_name(name: &str) -> CachePolicy {
+        match name {
+            "verify" => CachePolicy::Verify,
+            "ignore" => CachePolicy::Ignore,
+            _ => CachePolicy::Trust,
+        }
+    }
+}
+
+pub const GROUP_PREDICATES: &[&str] = &["same-dir", "hardlinks", "empty"];
+
+//Kind of group left out of the results, and of the action, by --exclude-group-if
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GroupPredicate {
+    //every member is in the same directory
+    SameDir,
+    //every member is the same file through hard links, there is nothing to reclaim
+    Hardlinks,
+    //the members are empty files
+    Empty,
+}
+
+impl GroupPredicate {
+    pub fn from_name(name: &str) -> Option<GroupPredicate> {
+        match name {
+            "same-dir" => Some(GroupPredicate::SameDir),
+            "hardlinks" => Some(GroupPredicate::Hardlinks),
+            "empty" => Some(GroupPredicate::Empty),
+            _ => None,
+        }
+    }
+
+    pub fn matches(&self, group: &DuplicateGroup) -> bool {
+        match self {
+            GroupPredicate::SameDir => {
+                let first = group.files.first().and_then(|f| f.parent());
+                group.files.iter().all(|f| f.parent() == first)
+            },
+            GroupPredicate::Hardlinks => {
+                let first = group.files.first().and_then(|f| file_id(f));
+                first.is_some() && group.files.iter().all(|f| file_id(f) == first)
+            },
+            GroupPredicate::Empty => group.size == Some(0),
+        }
+    }
+}
+
+//Device and inode of a file, only known on unix platforms
+#[cfg(unix)]
+fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).ok().map(|m| (m.dev(), m.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+/*
+ * Sign that a file was already handled, for --skip-marked: either an extended attribute
+ * set on it (`xattr:user.done`) or a sibling file named after it plus a suffix
+ * (`sibling:.done`, photo.jpg being marked by photo.jpg.done).
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Marker {
+    Xattr(String),
+    Sibling(String),
+}
+
+impl Marker {
+    pub fn from_spec(spec: &str) -> Option<Marker> {
+        match spec.split_once(':') {
+            Some(("xattr", name)) if !name.is_empty() => Some(Marker::Xattr(String::from(name))),
+            Some(("sibling", suffix)) if !suffix.is_empty() => Some(Marker::Sibling(String::from(suffix))),
+            _ => None,
+        }
+    }
+
+    //The marker files themselves are left out along the files they mark
+    pub fn marks(&self, path: &Path) -> bool {
+        match self {
+            Marker::Xattr(name) => xattr::get(path, name).ok().flatten().is_some(),
+            Marker::Sibling(suffix) => {
+                let mut sibling = path.as_os_str().to_os_string();
+                sibling.push(suffix);
+                let is_marker = path.to_string_lossy().strip_suffix(suffix.as_str()).is_some_and(|marked| Path::new(marked).exists());
+                is_marker || Path::new(&sibling).exists()
+            },
+        }
+    }
+}
+
+//Which of the walked files enter the comparison
+#[derive(Debug, Clone, Default)]
+pub struct Filters {
+    pub min_links: Option<u64>,
+    pub max_links: Option<u64>,
+    //full path must match one of them
+    pub path_regex: Option<RegexSet>,
+    //full path must match none of them
+    pub path_regex_exclude: Option<RegexSet>,
+    //leave out the directories on network filesystems, only warned about otherwise
+    pub skip_network: bool,
+    //only the files right inside the roots, subdirectories aren't walked
+    pub no_recursion: bool,
+    //symbolic links are followed one hop to a regular file, never to a directory or another link
+    pub dereference_once: bool,
+    //content type sniffed from the first bytes must match one of them (see mime_matches)
+    pub mime: Vec<String>,
+    //content type sniffed from the first bytes must match none of them
+    pub skip_mime: Vec<String>,
+    //exact sizes left out, however many files have them
+    pub exclude_sizes: Vec<u64>,
+    //files carrying any of these markers are left out
+    pub skip_marked: Vec<Marker>,
+    //modified at or after this time
+    pub newer_than: Option<SystemTime>,
+    //modified before this time
+    pub older_than: Option<SystemTime>,
+}
+
+//Number of hard links pointing to the file, only known on unix platforms
+#[cfg(unix)]
+fn link_count(entry: &DirEntry) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    entry.metadata().ok().map(|m| m.nlink())
+}
+
+#[cfg(not(unix))]
+fn link_count(_entry: &DirEntry) -> Option<u64> {
+    None
+}
+
+/*
+ * A mime pattern is either a full type (`video/mp4`) or a top-level type alone
+ * (`video`, or `video/` followed by a star) matching all of its subtypes.
+ */
+pub fn mime_matches(pattern: &str, mime: &str) -> bool {
+    let top = pattern.strip_suffix("/*").unwrap_or(pattern);
+    if top.contains('/') {
+        pattern.eq_ignore_ascii_case(mime)
+    } else {
+        mime.split('/').next().is_some_and(|t| t.eq_ignore_ascii_case(top))
+    }
+}
+
+/*
+ * A time of --newer-than or --older-than: the mtime of a reference file given as `@path`,
+ * a UTC date (`2024-03-01`, `2024-03-01 12:00:00` or RFC 3339), or a duration before now (`2 days`).
+ */
+pub fn parse_time(spec: &str) -> Result<SystemTime, String> {
+    if let Some(path) = spec.strip_prefix('@') {
+        let metadata = fs::metadata(path).map_err(|e| format!("reference file {}: {}", path, e))?;
+        return metadata.modified().map_err(|e| format!("reference file {}: {}", path, e));
+    }
+    let date = if spec.len() == 10 { format!("{} 00:00:00", spec) } else { String::from(spec) };
+    if let Ok(time) = humantime::parse_rfc3339_weak(&date) {
+        return Ok(time);
+    }
+    let ago = humantime::parse_duration(spec).map_err(|_| format!("{} is neither @path, a date nor a duration", spec))?;
+    SystemTime::now().checked_sub(ago).ok_or_else(|| format!("{} goes back too far", spec))
+}
+
+impl Filters {
+    pub fn accepts(&self, entry: &DirEntry) -> bool {
+        self.rejection(entry).is_none()
+    }
+
+    //The filter leaving the entry out, checked in the order of the cheapest first
+    pub fn rejection(&self, entry: &DirEntry) -> Option<&'static str> {
+        if let Some(links) = link_count(entry) {
+            if self.min_links.is_some_and(|m| links < m) {
+                return Some("fewer links than --min-links");
+            }
+            if self.max_links.is_some_and(|m| links > m) {
+                return Some("more links than --max-links");
+            }
+        }
+        if !self.exclude_sizes.is_empty() && !entry.metadata().is_ok_and(|m| !self.exclude_sizes.contains(&m.len())) {
+            return Some("size excluded by --exclude-size");
+        }
+        if self.newer_than.is_some() || self.older_than.is_some() {
+            let mtime = entry.metadata().ok().and_then(|m| m.modified().ok());
+            if self.newer_than.is_some_and(|t| mtime.is_none_or(|m| m < t)) {
+                return Some("modified before --newer-than");
+            }
+            if self.older_than.is_some_and(|t| mtime.is_none_or(|m| m >= t)) {
+                return Some("modified after --older-than");
+            }
+        }
+        let path = entry.path().to_string_lossy();
+        if !self.path_regex.as_ref().is_none_or(|r| r.is_match(&path)) {
+            return Some("path not matching --path-regex");
+        }
+        if self.path_regex_exclude.as_ref().is_some_and(|r| r.is_match(&path)) {
+            return Some("path matching --path-regex-exclude");
+        }
+        if self.skip_marked.iter().any(|m| m.marks(entry.path())) {
+            return Some("marked as handled (--skip-marked)");
+        }
+        self.mime_rejection(entry)
+    }
+
+    //Sniffing opens the file, so it is only done when a mime filter asks for it
+    fn mime_rejection(&self, entry: &DirEntry) -> Option<&'static str> {
+        if self.mime.is_empty() && self.skip_mime.is_empty() {
+            return None;
+        }
+        //An unknown type matches no pattern
+        let mime = infer::get_from_path(entry.path()).ok().flatten().map(|t| t.mime_type());
+        let matches = |patterns: &[String]| mime.is_some_and(|m| patterns.iter().any(|p| mime_matches(p, m)));
+        if !self.mime.is_empty() && !matches(&self.mime) {
+            return Some("content type not matching --mime");
+        }
+        if matches(&self.skip_mime) {
+            return Some("content type matching --skip-mime");
+        }
+        None
+    }
+}
+
+/*
+ * Everything a run needs besides the roots. Start from the default (name comparison,
+ * no filter, report only) and update the fields needed:
+ *
+ *     let options = Options { mode: Mode::Hashes, timeout: Some(Duration::from_secs(600)), ..Options::default() };
+ */
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub mode: Mode,
+    //compare names in Unicode NFC form (n and s)
+    pub normalize_unicode: bool,
+    //walk twice, counting the names first so only the repeated ones are kept (n, see find::for_each_by_repeated_names)
+    pub two_pass: bool,
+    pub hash: HashOptions,
+    pub filters: Filters,
+    //bytes per second read while comparing contents
+    pub max_read_rate: Option<u64>,
+    //stop scanning and comparing after this long
+    pub timeout: Option<Duration>,
+    //SQLite database holding the h grouping state instead of memory (see index::Index)
+    pub index: Option<PathBuf>,
+    pub cache_policy: CachePolicy,
+    //estimated memory of the h grouping past which it moves to a temporary index
+    pub memory_limit: Option<u64>,
+    pub ownership: OwnershipCheck,
+    //extended attributes that have to be the same for files to group (see xattrs::split)
+    pub match_xattr: Vec<String>,
+    //groups matching any of them are dropped
+    pub exclude_groups: Vec<GroupPredicate>,
+    pub action: Action,
+    pub keep: Keep,
+    //canonical directories the copy kept is taken from first, in order of preference (see action::plan)
+    pub keep_in: Vec<PathBuf>,
+    //file the operations done by the action are appended to (see action::ActionLog)
+    pub action_log: Option<PathBuf>,
+    //targets left for review unless include_risky
+    pub risks: Risks,
+    pub include_risky: bool,
+    //tag every member of the groups with the root it was found under (see DuplicateGroup::annotate_roots)
+    pub relative_to_roots: bool,
+    //sha256sum file written with the hash of every file (h, see manifest::Manifest)
+    pub checksum_output: Option<PathBuf>,
+    //give up on the run once more files than this were walked
+    pub max_files: Option<u64>,
+    //tell on stderr for every walked file whether it is compared, or what left it out
+    pub dump_candidates: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            mode: Mode::Names,
+            normalize_unicode: false,
+            two_pass: false,
+            hash: HashOptions::default(),
+            filters: Filters::default(),
+            max_read_rate: None,
+            timeout: None,
+            index: None,
+            cache_policy: CachePolicy::Trust,
+            memory_limit: None,
+            ownership: OwnershipCheck::Ignore,
+            match_xattr: Vec::new(),
+            exclude_groups: Vec::new(),
+            action: Action::Report,
+            keep: Keep::First,
+            keep_in: Vec::new(),
+            action_log: None,
+            risks: Risks::default(),
+            include_risky: false,
+            relative_to_roots: false,
+            checksum_output: None,
+            max_files: None,
+            dump_candidates: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn action_names() {
+        for name in ACTIONS.iter() {
+            assert_eq!(Action::from_name(name, false).name(), *name);
+        }
+        assert_eq!(Action::from_name("trash", true), Action::Trash { dry_run: true });
+        assert_eq!(Action::from_name("reflink", false), Action::Reflink { dry_run: false });
+    }
+}
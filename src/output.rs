@@ -0,0 +1,630 @@
+use std::fs;
+use std::io::{self, BufWriter, Error, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use serde::{Serialize, Deserialize};
+use crate::fclones;
+use crate::group::DuplicateGroup;
+use crate::interrupt;
+use crate::template::Template;
+use crate::tree;
+
+//Descriptor of the standard output, for interrupt::close_on_signal
+const STDOUT_FD: i32 = 1;
+
+//Bumped when a field of the JSON document or of DuplicateGroup is removed or changes meaning,
+//fields are only ever added within a version
+pub const FORMAT_VERSION: u32 = 1;
+
+pub const FORMATS: &[&str] = &["text", "json", "json-stream", "json-array", "groups", "tree", "fclones"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+    //One JSON object per line as the groups come: a header, the groups, then a trailer
+    JsonStream,
+    //The JSON document written as the groups come, valid once closed even when cut short
+    JsonArray,
+    //Paths of a group one per line, groups separated by a blank line
+    Groups,
+    //Directories holding duplicates with the space they would free
+    Tree,
+    //Report of fclones, for its actions (see fclones::write_report)
+    Fclones,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Format {
+        match name {
+            "json" => Format::Json,
+            "json-stream" => Format::JsonStream,
+            "json-array" => Format::JsonArray,
+            "groups" => Format::Groups,
+            "tree" => Format::Tree,
+            "fclones" => Format::Fclones,
+            _ => Format::Text,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tool {
+    pub name: String,
+    pub version: String,
+}
+
+/*
+ * Top level JSON document:
+ * {"version": 1, "tool": {"name", "version"}, "mode": "n"|"s"|"h"|"c", "algorithm": ..., "groups": [...]}
+ * where the algorithm is the one of the mode (see Mode::algorithm), such as "sha256",
+ * "sha256-ends" or "fuzzy-bytes", or null when the mode compares no content.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Document {
+    pub version: u32,
+    pub tool: Tool,
+    pub mode: String,
+    pub algorithm: Option<String>,
+    pub groups: Vec<DuplicateGroup>,
+}
+
+impl Document {
+    pub fn new(mode: &str, algorithm: Option<&str>, groups: Vec<DuplicateGroup>) -> Document {
+        Document {
+            version: FORMAT_VERSION,
+            tool: Tool {
+                name: String::from(env!("CARGO_PKG_NAME")),
+                version: String::from(env!("CARGO_PKG_VERSION")),
+            },
+            mode: String::from(mode),
+            algorithm: algorithm.map(String::from),
+            groups,
+        }
+    }
+}
+
+/*
+ * Presentation of the groups, fed one at a time as the comparison streams them.
+ * Formats needing every group first (the JSON document, the tree) keep them until
+ * `finish`. Library users can plug in their own.
+ */
+pub trait Formatter {
+    //Called once before the first group with the comparison the groups come from
+    fn begin(&mut self, _mode: &str, _algorithm: Option<&str>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn group(&mut self, group: DuplicateGroup) -> Result<(), Error>;
+
+    fn finish(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+pub struct TextFormatter<W: Write> {
+    out: W,
+    style: TextStyle,
+}
+
+impl<W: Write> TextFormatter<W> {
+    pub fn new(out: W, style: TextStyle) -> TextFormatter<W> {
+        TextFormatter { out, style }
+    }
+}
+
+impl<W: Write> Formatter for TextFormatter<W> {
+    fn group(&mut self, group: DuplicateGroup) -> Result<(), Error> {
+        write_text_group(&mut self.out, &group, self.style)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.out.flush()
+    }
+}
+
+pub struct GroupsFormatter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> GroupsFormatter<W> {
+    pub fn new(out: W) -> GroupsFormatter<W> {
+        GroupsFormatter { out }
+    }
+}
+
+impl<W: Write> Formatter for GroupsFormatter<W> {
+    fn group(&mut self, group: DuplicateGroup) -> Result<(), Error> {
+        write_groups_group(&mut self.out, &group)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.out.flush()
+    }
+}
+
+//The whole Document, written once every group is in
+pub struct JsonFormatter<W: Write> {
+    out: W,
+    document: Document,
+}
+
+impl<W: Write> JsonFormatter<W> {
+    pub fn new(out: W) -> JsonFormatter<W> {
+        JsonFormatter { out, document: Document::new("", None, Vec::new()) }
+    }
+}
+
+impl<W: Write> Formatter for JsonFormatter<W> {
+    fn begin(&mut self, mode: &str, algorithm: Option<&str>) -> Result<(), Error> {
+        self.document = Document::new(mode, algorithm, Vec::new());
+        Ok(())
+    }
+
+    fn group(&mut self, mut group: DuplicateGroup) -> Result<(), Error> {
+        group.id = Some(group.stable_id());
+        self.document.groups.push(group);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        serde_json::to_writer_pretty(&mut self.out, &self.document).map_err(Error::other)?;
+        writeln!(self.out)?;
+        self.out.flush()
+    }
+}
+
+//Closing of a stream cut short, by an error or a signal
+const LINES_INTERRUPTED: &str = "{\"complete\":false}\n";
+const ARRAY_INTERRUPTED: &str = "\n],\"complete\":false}\n";
+
+//First object of a stream, the Document without its groups
+#[derive(Serialize)]
+struct StreamHeader<'a> {
+    version: u32,
+    tool: Tool,
+    mode: &'a str,
+    algorithm: Option<&'a str>,
+}
+
+/*
+ * JSON written as the groups come, each one flushed, for consumers reading it while the
+ * run goes on. As lines, a header object comes first, then every group on a line of
+ * its own and last `{"complete":true,"groups":N}`. As an array, the Document comes with
+ * its groups one per line and ends with `"complete":true`. An output left open by an
+ * error, or by a signal when guarded (see closing_on_signal), is closed with
+ * `"complete":false` instead: a missing trailer tells a stream killed outright.
+ */
+pub struct JsonStreamFormatter<W: Write> {
+    out: W,
+    //one object per line rather than a single document
+    lines: bool,
+    count: usize,
+    begun: bool,
+}
+
+impl<W: Write> JsonStreamFormatter<W> {
+    pub fn new(out: W, lines: bool) -> JsonStreamFormatter<W> {
+        JsonStreamFormatter { out, lines, count: 0, begun: false }
+    }
+
+    //Close the output written to `fd` on SIGINT and SIGTERM too (see interrupt::close_on_signal)
+    pub fn closing_on_signal(self, fd: i32) -> JsonStreamFormatter<W> {
+        interrupt::close_on_signal(fd, if self.lines { LINES_INTERRUPTED } else { ARRAY_INTERRUPTED });
+        self
+    }
+
+    fn write(&mut self, text: &str) -> Result<(), Error> {
+        interrupt::writing();
+        self.out.write_all(text.as_bytes())?;
+        self.out.flush()?;
+        interrupt::written();
+        Ok(())
+    }
+}
+
+impl<W: Write> Formatter for JsonStreamFormatter<W> {
+    fn begin(&mut self, mode: &str, algorithm: Option<&str>) -> Result<(), Error> {
+        let header = StreamHeader {
+            version: FORMAT_VERSION,
+            tool: Tool {
+                name: String::from(env!("CARGO_PKG_NAME")),
+                version: String::from(env!("CARGO_PKG_VERSION")),
+            },
+            mode,
+            algorithm,
+        };
+        let mut text = serde_json::to_string(&header).map_err(Error::other)?;
+        if self.lines {
+            text.push('\n');
+        } else {
+            //The groups go inside the header object
+            text.pop();
+            text.push_str(",\"groups\":[");
+        }
+        self.begun = true;
+        self.write(&text)
+    }
+
+    fn group(&mut self, mut group: DuplicateGroup) -> Result<(), Error> {
+        group.id = Some(group.stable_id());
+        let object = serde_json::to_string(&group).map_err(Error::other)?;
+        let text = match (self.lines, self.count) {
+            (true, _) => format!("{}\n", object),
+            (false, 0) => format!("\n{}", object),
+            (false, _) => format!(",\n{}", object),
+        };
+        self.count += 1;
+        self.write(&text)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        let text = if self.lines {
+            format!("{{\"complete\":true,\"groups\":{}}}\n", self.count)
+        } else {
+            String::from("\n],\"complete\":true}\n")
+        };
+        self.write(&text)?;
+        self.begun = false;
+        interrupt::ended();
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for JsonStreamFormatter<W> {
+    fn drop(&mut self) {
+        if self.begun {
+            let closing = if self.lines { LINES_INTERRUPTED } else { ARRAY_INTERRUPTED };
+            let _ = self.out.write_all(closing.as_bytes()).and_then(|_| self.out.flush());
+            interrupt::ended();
+        }
+    }
+}
+
+pub struct TreeFormatter<W: Write> {
+    out: W,
+    color: bool,
+    groups: Vec<DuplicateGroup>,
+}
+
+impl<W: Write> TreeFormatter<W> {
+    pub fn new(out: W, color: bool) -> TreeFormatter<W> {
+        TreeFormatter { out, color, groups: Vec::new() }
+    }
+}
+
+impl<W: Write> Formatter for TreeFormatter<W> {
+    fn group(&mut self, group: DuplicateGroup) -> Result<(), Error> {
+        self.groups.push(group);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        tree::write_tree(&mut self.out, &self.groups, self.color)?;
+        self.out.flush()
+    }
+}
+
+//Only groups of identical files may reach fclones, which acts on them without checking
+fn identical_only(mode: &str, algorithm: Option<&str>) -> Result<(), Error> {
+    if algorithm != Some("sha256") {
+        return Err(Error::new(io::ErrorKind::InvalidInput, format!("--format fclones only takes groups of identical files, not of {} {}", mode, algorithm.unwrap_or("-"))));
+    }
+    Ok(())
+}
+
+pub struct FclonesFormatter<W: Write> {
+    out: W,
+    groups: Vec<DuplicateGroup>,
+}
+
+impl<W: Write> FclonesFormatter<W> {
+    pub fn new(out: W) -> FclonesFormatter<W> {
+        FclonesFormatter { out, groups: Vec::new() }
+    }
+}
+
+impl<W: Write> Formatter for FclonesFormatter<W> {
+    fn begin(&mut self, mode: &str, algorithm: Option<&str>) -> Result<(), Error> {
+        identical_only(mode, algorithm)
+    }
+
+    fn group(&mut self, group: DuplicateGroup) -> Result<(), Error> {
+        self.groups.push(group);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        fclones::write_report(&mut self.out, &self.groups)?;
+        self.out.flush()
+    }
+}
+
+//One line per member rendered from a --template, the relative paths taken under the deepest root
+pub struct TemplateFormatter<W: Write> {
+    out: W,
+    template: Template,
+    roots: Vec<PathBuf>,
+    count: usize,
+}
+
+impl<W: Write> TemplateFormatter<W> {
+    pub fn new(out: W, template: Template, roots: &[PathBuf]) -> TemplateFormatter<W> {
+        TemplateFormatter { out, template, roots: roots.to_vec(), count: 0 }
+    }
+}
+
+impl<W: Write> Formatter for TemplateFormatter<W> {
+    fn group(&mut self, group: DuplicateGroup) -> Result<(), Error> {
+        self.count += 1;
+        for f in group.files.iter() {
+            let relpath = self.roots.iter()
+                .filter_map(|r| f.strip_prefix(r).ok())
+                .min_by_key(|r| r.components().count())
+                .unwrap_or(f);
+            writeln!(self.out, "{}", self.template.render(self.count, &group, f, relpath))?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.out.flush()
+    }
+}
+
+/*
+ * Formatter of a --format printing on stdout, the tree being colored on a terminal.
+ * Streams are closed on a signal when `guarded`, which a run acting on files isn't: the
+ * exit would leave its lock and a half made clone behind.
+ */
+pub fn formatter(format: Format, style: TextStyle, guarded: bool) -> Box<dyn Formatter> {
+    let stdout = io::stdout();
+    match format {
+        Format::Text => Box::new(TextFormatter::new(stdout, style)),
+        Format::Groups => Box::new(GroupsFormatter::new(stdout)),
+        Format::Json => Box::new(JsonFormatter::new(stdout)),
+        Format::JsonStream | Format::JsonArray => {
+            let stream = JsonStreamFormatter::new(stdout, format == Format::JsonStream);
+            Box::new(if guarded { stream.closing_on_signal(STDOUT_FD) } else { stream })
+        },
+        Format::Tree => {
+            let color = stdout.is_terminal() && std::env::var_os("NO_COLOR").is_none();
+            Box::new(TreeFormatter::new(stdout, color))
+        },
+        Format::Fclones => Box::new(FclonesFormatter::new(stdout)),
+    }
+}
+
+pub fn print_groups(document: Document, format: Format, style: TextStyle) -> Result<(), Error> {
+    let mut formatter = formatter(format, style, true);
+    formatter.begin(&document.mode, document.algorithm.as_deref())?;
+    for group in document.groups.into_iter() {
+        formatter.group(group)?;
+    }
+    formatter.finish()
+}
+
+//How a modification time is shown in the text output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeFormat {
+    //modified 3 days ago
+    Relative,
+    //modified 2024-01-01T12:00:00Z
+    Iso,
+}
+
+//Extras of the text output
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TextStyle {
+    //lead group headers with their hex digest, as sha256sum prints it
+    pub show_hash: bool,
+    //show the modification time of every member
+    pub mtime: Option<TimeFormat>,
+}
+
+fn modified(path: &Path, format: TimeFormat) -> Option<String> {
+    let mtime = fs::symlink_metadata(path).and_then(|m| m.modified()).ok()?;
+    match format {
+        TimeFormat::Iso => Some(format!("modified {}", humantime::format_rfc3339_seconds(mtime))),
+        TimeFormat::Relative => {
+            let age = SystemTime::now().duration_since(mtime).unwrap_or_default();
+            Some(format!("modified {}", format_age(age)))
+        },
+    }
+}
+
+//Age in its biggest whole unit, a copy made three days ago being one made "3 days ago"
+pub fn format_age(age: Duration) -> String {
+    const UNITS: &[(u64, &str)] = &[(365 * 86400, "year"), (30 * 86400, "month"), (86400, "day"), (3600, "hour"), (60, "minute")];
+    let seconds = age.as_secs();
+    for (length, unit) in UNITS.iter() {
+        let count = seconds / length;
+        if count > 0 {
+            return format!("{} {}{} ago", count, unit, if count == 1 { "" } else { "s" });
+        }
+    }
+    String::from("just now")
+}
+
+pub fn print_text_group(group: &DuplicateGroup, style: TextStyle) -> Result<(), Error> {
+    write_text_group(&mut io::stdout().lock(), group, style)
+}
+
+pub fn write_text_group<W: Write>(out: &mut W, group: &DuplicateGroup, style: TextStyle) -> Result<(), Error> {
+    let digest = match group.hash.as_ref() {
+        Some(hash) if style.show_hash => format!("{}  ", hash),
+        _ => String::new(),
+    };
+    let relative = relative_paths(group);
+    let mut notes = Vec::new();
+    if let Some(files) = group.directory_files {
+        notes.push(format!("directories of {} files", files));
+    }
+    if let Some(overlap) = group.overlap {
+        notes.push(format!("{:.1}% shared", overlap));
+    } else if group.suspect {
+        notes.push(String::from("suspect, unreadable parts"));
+    }
+    if same_relative_path(group, &relative) {
+        notes.push(String::from("same relative path"));
+    }
+    if renamed(group) {
+        notes.push(String::from("renamed"));
+    }
+    if notes.is_empty() {
+        writeln!(out, "{digest}{filename}:",digest=digest,filename=group.name)?;
+    } else {
+        writeln!(out, "{digest}{filename} ({notes}):",digest=digest,filename=group.name,notes=notes.join(", "))?;
+    }
+    for (i, f) in group.files.iter().enumerate() {
+        let source = match group.sources.as_ref().and_then(|s| s.get(i)) {
+            Some(label) => format!("[{}] ", label),
+            None => String::new(),
+        };
+        let filepath = relative.as_ref().map_or(f.as_path(), |r| r[i]).to_string_lossy();
+        let mut notes = Vec::new();
+        if group.truncated.as_ref() == Some(f) {
+            notes.push(String::from("truncated"));
+        } else if group.compressed.contains(f) {
+            notes.push(String::from("compressed"));
+        } else if let Some(o) = group.ownership.as_ref().and_then(|o| o.get(i)) {
+            notes.push(format!("mode {:o}, uid {}, gid {}", o.mode, o.uid, o.gid));
+        }
+        if group.survivor.as_ref() == Some(f) {
+            notes.push(String::from("kept"));
+        }
+        if let Some(modified) = style.mtime.and_then(|t| modified(f, t)) {
+            notes.push(modified);
+        }
+        if notes.is_empty() {
+            writeln!(out, "\t{source}{filepath}",source=source,filepath=filepath)?;
+        } else {
+            writeln!(out, "\t{source}{filepath} ({notes})",source=source,filepath=filepath,notes=notes.join(", "))?;
+        }
+    }
+    Ok(())
+}
+
+//Paths of the members under their source, when every source is a root holding its member
+fn relative_paths(group: &DuplicateGroup) -> Option<Vec<&Path>> {
+    let sources = group.sources.as_ref()?;
+    group.files.iter().zip(sources.iter())
+        .map(|(f, source)| if source.is_empty() { None } else { f.strip_prefix(source).ok() })
+        .collect()
+}
+
+//The same file in several snapshots: one relative path, under different roots
+fn same_relative_path(group: &DuplicateGroup, relative: &Option<Vec<&Path>>) -> bool {
+    match (relative, group.sources.as_ref()) {
+        (Some(relative), Some(sources)) => {
+            relative.iter().all(|r| *r == relative[0]) && sources.iter().any(|s| *s != sources[0])
+        },
+        _ => false,
+    }
+}
+
+/*
+ * Same content under different file names, as grouped by diff, --ignore-extension or
+ * --decompress: renamed copies rather than exact ones. Only groups with a hash are
+ * concerned, names alone prove nothing of the content.
+ */
+fn renamed(group: &DuplicateGroup) -> bool {
+    group.hash.is_some() && group.files.iter().any(|f| f.file_name() != group.files[0].file_name())
+}
+
+pub fn write_groups_group<W: Write>(out: &mut W, group: &DuplicateGroup) -> Result<(), Error> {
+    for f in group.files.iter() {
+        writeln!(out, "{}", f.to_string_lossy())?;
+    }
+    writeln!(out)
+}
+
+/*
+ * Output of every group to its own file of a directory, in the chosen format.
+ * Files are named after the group position, an index.tsv lists for each file
+ * the group name, hash (or -), number of members and stable id.
+ */
+pub struct SplitOutput {
+    dir: PathBuf,
+    format: Format,
+    style: TextStyle,
+    count: usize,
+    index: BufWriter<fs::File>,
+}
+
+impl SplitOutput {
+    pub fn new(dir: &Path, format: Format, style: TextStyle) -> Result<SplitOutput, Error> {
+        fs::create_dir_all(dir)?;
+        let index = BufWriter::new(fs::File::create(dir.join("index.tsv"))?);
+        Ok(SplitOutput {
+            dir: dir.to_path_buf(),
+            format,
+            style,
+            count: 0,
+            index,
+        })
+    }
+
+}
+
+impl Formatter for SplitOutput {
+    fn begin(&mut self, mode: &str, algorithm: Option<&str>) -> Result<(), Error> {
+        match self.format {
+            Format::Fclones => identical_only(mode, algorithm),
+            _ => Ok(()),
+        }
+    }
+
+    fn group(&mut self, group: DuplicateGroup) -> Result<(), Error> {
+        self.count += 1;
+        let extension = match self.format {
+            Format::Text | Format::Groups | Format::Tree | Format::Fclones => "txt",
+            Format::Json | Format::JsonStream | Format::JsonArray => "json",
+        };
+        let filename = format!("{:06}.{}", self.count, extension);
+        let mut out = BufWriter::new(fs::File::create(self.dir.join(&filename))?);
+        match self.format {
+            //A tree of a single group would only repeat its paths
+            Format::Text | Format::Tree => write_text_group(&mut out, &group, self.style)?,
+            Format::Groups => write_groups_group(&mut out, &group)?,
+            Format::Fclones => fclones::write_report(&mut out, std::slice::from_ref(&group))?,
+            Format::Json | Format::JsonStream | Format::JsonArray => {
+                let group = DuplicateGroup { id: Some(group.stable_id()), ..group.clone() };
+                serde_json::to_writer_pretty(&mut out, &group).map_err(Error::other)?;
+                writeln!(out)?;
+            },
+        }
+        out.flush()?;
+        writeln!(self.index, "{}\t{}\t{}\t{}\t{}", filename, group.name, group.hash.as_deref().unwrap_or("-"), group.files.len(), group.stable_id())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.index.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::group::Ownership;
+
+    #[test]
+    fn document_round_trip() {
+        let plain = DuplicateGroup::new(String::from("a"), None, None, vec![PathBuf::from("x/a"), PathBuf::from("y/a")]);
+        let mut full = DuplicateGroup::new(String::from("b"), Some(3), Some(String::from("ab")), vec![PathBuf::from("x/b"), PathBuf::from("y/b")]);
+        full.truncated = Some(PathBuf::from("y/b"));
+        full.overlap = Some(50.0);
+        full.ownership = Some(vec![Ownership { mode: 0o644, uid: 1, gid: 2 }; 2]);
+        full.sources = Some(vec![String::from("x"), String::from("y")]);
+        full.suspect = true;
+        full.compressed = vec![PathBuf::from("y/b")];
+        full.survivor = Some(PathBuf::from("x/b"));
+        full.directory_files = Some(4);
+        full.id = Some(full.stable_id());
+        let document = Document::new("h", Some("sha256"), vec![plain, full]);
+        let json = serde_json::to_string(&document).unwrap();
+        let read : Document = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, document);
+        assert_eq!(read.version, FORMAT_VERSION);
+    }
+}
@@ -0,0 +1,46 @@
+use std::io::Error;
+
+//Niceness set by --nice, the lowest CPU priority
+#[cfg(unix)]
+const NICE_VALUE: libc::c_int = 19;
+
+//From linux/ioprio.h, which libc doesn't carry
+#[cfg(target_os = "linux")]
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+#[cfg(target_os = "linux")]
+const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+#[cfg(target_os = "linux")]
+const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+/*
+ * Lower the CPU and I/O priority of the whole process, so a background scan gives way
+ * to foreground work: niceness 19 and the idle I/O class, which only gets the disk when
+ * nobody else wants it. Raising them back needs privileges, it lasts until the end.
+ */
+#[cfg(target_os = "linux")]
+pub fn lower() -> Result<(), Error> {
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, NICE_VALUE) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    let ioprio = IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT;
+    if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+//Other unix systems have a niceness but no I/O class
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn lower() -> Result<(), Error> {
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, NICE_VALUE) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    eprintln!("warning: --nice only lowers the CPU priority on this platform, not the I/O one");
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn lower() -> Result<(), Error> {
+    eprintln!("warning: --nice isn't supported on this platform, the priority is left as is");
+    Ok(())
+}
@@ -0,0 +1,177 @@
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::io::Error;
+use std::path::PathBuf;
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use crate::action::{self, ActionStep};
+use crate::filesystem::Disk;
+use crate::group::DuplicateGroup;
+use crate::options::Keep;
+use crate::output::Document;
+
+//Figures about a run
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stats {
+    //files that entered the comparison
+    pub files_scanned: u64,
+    //files left out by the filters
+    pub files_skipped: u64,
+    //entries the walk couldn't read
+    pub errors: u64,
+    //bytes read to compare contents
+    pub bytes_hashed: u64,
+    //files read again after a transient error
+    pub files_retried: u64,
+    //groups found, after the group filters
+    pub groups: u64,
+    //members of the groups beyond the first one of each
+    pub redundant_files: u64,
+    //space taken by the redundant files, for the groups whose size is known
+    pub reclaimable_bytes: u64,
+    //walking the roots, including the grouping by name and size done along
+    pub walk_time: Duration,
+    //comparing the contents once the walk is over
+    pub compare_time: Duration,
+    //applying the action on the groups
+    pub action_time: Duration,
+}
+
+//Outcome of a run
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub mode: String,
+    pub algorithm: Option<String>,
+    //empty when the groups were streamed to a callback
+    pub groups: Vec<DuplicateGroup>,
+    //steps done, or only planned in a dry run, by the action
+    pub steps: Vec<ActionStep>,
+    //the timeout stopped the run, results are partial
+    pub timed_out: bool,
+    //--max-files stopped the walk, nothing was compared nor acted on
+    pub capped: bool,
+    pub stats: Stats,
+}
+
+impl Report {
+    pub fn document(self) -> Document {
+        Document::new(&self.mode, self.algorithm.as_deref(), self.groups)
+    }
+
+    pub fn summary(&self) -> Summary {
+        let stats = &self.stats;
+        Summary {
+            groups: stats.groups,
+            redundant_files: stats.redundant_files,
+            reclaimable_bytes: stats.reclaimable_bytes,
+            files_scanned: stats.files_scanned,
+            errors: stats.errors,
+            files_retried: stats.files_retried,
+            elapsed_ms: (stats.walk_time + stats.compare_time + stats.action_time).as_millis() as u64,
+            timed_out: self.timed_out,
+        }
+    }
+}
+
+//Figures of a run for scripts, as printed by --stats-json
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    pub groups: u64,
+    pub redundant_files: u64,
+    pub reclaimable_bytes: u64,
+    pub files_scanned: u64,
+    pub errors: u64,
+    #[serde(default)]
+    pub files_retried: u64,
+    pub elapsed_ms: u64,
+    pub timed_out: bool,
+}
+
+/*
+ * The `n` biggest redundant files of the groups, each with the copy it duplicates,
+ * biggest first. Files are ranked on their own, not by the total of their group, and
+ * are the ones an action would reclaim (see action::plan).
+ */
+pub fn top_files(groups: &[DuplicateGroup], n: usize, keep: Keep, homes: &[PathBuf]) -> Result<Vec<ActionStep>, Error> {
+    let mut steps = Vec::new();
+    for group in groups.iter() {
+        steps.extend(action::plan(&Disk, group, keep, homes)?);
+    }
+    steps.sort_by_key(|s| Reverse(s.size));
+    steps.truncate(n);
+    Ok(steps)
+}
+
+//Space taken by the content the steps of an action act on, before and after them
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Savings {
+    pub before: u64,
+    pub after: u64,
+}
+
+impl Savings {
+    //Each survivor is kept and counted once, hard linked targets only count once
+    pub fn of(steps: &[ActionStep]) -> Savings {
+        let mut survivors = HashSet::new();
+        let mut savings = Savings::default();
+        for step in steps.iter() {
+            if survivors.insert(&step.survivor) {
+                savings.before += step.size;
+                savings.after += step.size;
+            }
+            if !step.linked {
+                savings.before += step.size;
+            }
+        }
+        savings
+    }
+
+    pub fn reclaimed(&self) -> u64 {
+        self.before - self.after
+    }
+}
+
+//Distribution of the number of copies per group, fed one group at a time
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GroupStats {
+    counts: Vec<usize>,
+    //name and number of members of the biggest group
+    largest: Option<(String, usize)>,
+}
+
+impl GroupStats {
+    pub fn add(&mut self, group: &DuplicateGroup) {
+        let count = group.files.len();
+        self.counts.push(count);
+        if self.largest.as_ref().is_none_or(|l| count > l.1) {
+            self.largest = Some((group.name.clone(), count));
+        }
+    }
+
+    pub fn groups(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn files(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    //Middle value of the copies per group, the mean of the two middle ones for an even count
+    pub fn median(&self) -> Option<f64> {
+        if self.counts.is_empty() {
+            return None;
+        }
+        let mut counts = self.counts.clone();
+        counts.sort_unstable();
+        let middle = counts.len() / 2;
+        if counts.len().is_multiple_of(2) {
+            Some((counts[middle - 1] + counts[middle]) as f64 / 2.0)
+        } else {
+            Some(counts[middle] as f64)
+        }
+    }
+
+    pub fn largest(&self) -> Option<(&str, usize)> {
+        self.largest.as_ref().map(|l| (l.0.as_str(), l.1))
+    }
+}
@@ -0,0 +1,382 @@
+use std::cell::Cell;
+use std::fs;
+use std::io::Error;
+use std::time::{Duration, Instant};
+use std::path::PathBuf;
+use walkdir::{DirEntry, WalkDir};
+use crate::action::{self, ActionLog};
+use crate::chunked;
+use crate::command;
+use crate::deadline::Deadline;
+use crate::decompress;
+use crate::directories;
+use crate::filesystem::Disk;
+use crate::find;
+use crate::lock::LOCK_NAME;
+use crate::manifest::Manifest;
+use crate::media;
+use crate::netfs::NetworkGuard;
+use crate::group::DuplicateGroup;
+use crate::index::{self, Index, TemporaryIndex};
+use crate::options::{Action, Filters, Mode, Options, OwnershipCheck};
+use crate::report::{Report, Stats};
+use crate::throttle::Throttle;
+use crate::xattrs;
+
+/*
+ * Under --dereference-once, a link below the roots is kept when what it points to
+ * directly is a regular file: a link to a directory is pruned before the walk goes
+ * into it, a link to another link (or to nothing) is left out without being chased.
+ */
+fn one_hop(entry: &DirEntry) -> bool {
+    if !entry.path_is_symlink() || entry.depth() == 0 {
+        return true;
+    }
+    let target = match fs::read_link(entry.path()) {
+        Ok(target) => target,
+        Err(_) => return false,
+    };
+    //A relative target is relative to the directory of the link
+    let target = entry.path().parent().map(|p| p.join(&target)).unwrap_or(target);
+    fs::symlink_metadata(target).is_ok_and(|m| m.file_type().is_file())
+}
+
+/*
+ * Following links, the walk reports a link to one of its ancestors as a loop and a
+ * dangling link as unreadable before one_hop can see them. Both are links one_hop
+ * leaves out, not errors.
+ */
+fn left_out_link(error: &walkdir::Error) -> bool {
+    error.loop_ancestor().is_some() || error.path().is_some_and(|p| fs::symlink_metadata(p).is_ok_and(|m| m.file_type().is_symlink()))
+}
+
+//Entries under the roots but the lock files, network filesystems warned about or pruned
+fn walk<'a>(filters: &'a Filters, roots: &'a [PathBuf]) -> impl Iterator<Item= walkdir::Result<DirEntry>> + 'a {
+    roots.iter().flat_map(move |root| {
+        let mut guard = NetworkGuard::new(filters.skip_network);
+        //A root given as a symlink (e.g. latest -> backup-3) is always walked into, links below it only
+        //followed under --dereference-once, to a file and never further (see one_hop)
+        let depth = if filters.no_recursion { 1 } else { usize::MAX };
+        let dereference_once = filters.dereference_once;
+        WalkDir::new(root).follow_root_links(true).follow_links(dereference_once).max_depth(depth).into_iter()
+            .filter_entry(move |e| e.file_name() != LOCK_NAME && (!dereference_once || one_hop(e)) && guard.keeps(e))
+            .filter(move |e| !dereference_once || e.as_ref().err().is_none_or(|e| !left_out_link(e)))
+    })
+}
+
+//Every file under the roots that passes the filters, until the deadline
+pub fn files<'a>(filters: &'a Filters, roots: &'a [PathBuf], deadline: &'a Deadline) -> impl Iterator<Item= DirEntry> + 'a {
+    walk(filters, roots)
+        .filter_map(Result::ok)
+        .filter(|e| !find::is_directory(e))
+        .filter(move |e| filters.accepts(e))
+        .take_while(move |_| !deadline.expired())
+}
+
+//Counts gathered while the walk is consumed by a comparison
+#[derive(Default)]
+struct WalkCounters {
+    scanned: Cell<u64>,
+    skipped: Cell<u64>,
+    errors: Cell<u64>,
+    end: Cell<Option<Instant>>,
+    //more files than Options::max_files were found, the walk was stopped
+    capped: Cell<bool>,
+}
+
+//Why the comparison itself will pass over a file the filters let through
+fn comparison_rejection(options: &Options, entry: &DirEntry) -> Option<&'static str> {
+    let size = entry.metadata().ok()?.len();
+    if options.mode.name() != "h" || !options.hash.skips(size) {
+        return None;
+    }
+    if options.hash.skip_big && size > find::BIG_FILE_SIZE {
+        Some("bigger than the big files size (-b)")
+    } else {
+        Some("smaller than the small files size (-s)")
+    }
+}
+
+fn counted_files<'a>(options: &'a Options, roots: &'a [PathBuf], deadline: &'a Deadline, counters: &'a WalkCounters) -> impl Iterator<Item= DirEntry> + 'a {
+    let filters = &options.filters;
+    let max_files = options.max_files;
+    let mut iter = walk(filters, roots)
+        .filter_map(move |e| {
+            if let Err(error) = e.as_ref() {
+                counters.errors.set(counters.errors.get() + 1);
+                if options.dump_candidates {
+                    eprintln!("unreadable: {}", error);
+                }
+            }
+            e.ok()
+        })
+        .filter(|e| !find::is_directory(e))
+        .filter(move |e| {
+            let rejection = filters.rejection(e);
+            if rejection.is_some() {
+                counters.skipped.set(counters.skipped.get() + 1);
+            }
+            if options.dump_candidates {
+                match rejection.or_else(|| comparison_rejection(options, e)) {
+                    Some(reason) => eprintln!("skipped {}: {}", e.path().to_string_lossy(), reason),
+                    None => eprintln!("candidate {}", e.path().to_string_lossy()),
+                }
+            }
+            rejection.is_none()
+        })
+        .take_while(move |_| !deadline.expired());
+    std::iter::from_fn(move || {
+        let next = iter.next();
+        //The cap stops the walk and, through the deadline, what would follow it
+        if next.is_some() && max_files.is_some_and(|max| counters.scanned.get() >= max) {
+            counters.capped.set(true);
+            counters.end.set(Some(Instant::now()));
+            deadline.expire();
+            return None;
+        }
+        match next {
+            Some(_) => counters.scanned.set(counters.scanned.get() + 1),
+            None if counters.end.get().is_none() => counters.end.set(Some(Instant::now())),
+            None => {},
+        }
+        next
+    })
+}
+
+//Files of the first walk of `n --two-pass`, which --max-files caps as the counted walk that follows it
+fn first_pass<'a>(options: &'a Options, roots: &'a [PathBuf], deadline: &'a Deadline, counters: &'a WalkCounters) -> impl Iterator<Item= DirEntry> + 'a {
+    let mut found = 0;
+    files(&options.filters, roots, deadline).take_while(move |_| {
+        found += 1;
+        if options.max_files.is_some_and(|max| found > max) {
+            counters.capped.set(true);
+            deadline.expire();
+            return false;
+        }
+        true
+    })
+}
+
+//Memory taken by a walked file until it is grouped, besides its path: the entry and its bucket (an estimate)
+const ENTRY_FOOTPRINT: u64 = 160;
+
+/*
+ * Pull the files from the walk as long as their estimated footprint stays under
+ * `limit`: Ok with all of them when the walk ended first, Err with the ones pulled
+ * so far otherwise, the others being left in `iter`.
+ */
+fn within_memory<I: Iterator<Item= DirEntry>>(iter: &mut I, limit: u64) -> Result<Vec<DirEntry>, Vec<DirEntry>> {
+    let mut files = Vec::new();
+    let mut footprint = 0;
+    for entry in iter.by_ref() {
+        //The path is kept by the entry and once more in the name key
+        footprint += ENTRY_FOOTPRINT + 2 * entry.path().as_os_str().len() as u64;
+        files.push(entry);
+        if footprint > limit {
+            return Err(files);
+        }
+    }
+    Ok(files)
+}
+
+fn hashes_in_memory<I, F>(iter: I, options: &Options, manifest: Option<&mut Manifest>, throttle: &mut Throttle, deadline: &Deadline, emit: F) -> Result<(), Error>
+where I: IntoIterator<Item= DirEntry>,
+      F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    match manifest {
+        Some(manifest) => find::for_each_by_hashes_listed(iter, options.hash, manifest, throttle, deadline, emit),
+        None => find::for_each_by_hashes(iter, options.hash, throttle, deadline, emit),
+    }
+}
+
+//Run the comparison and action, collecting the groups into the report
+pub fn run(options: &Options, roots: &[PathBuf]) -> Result<Report, Error> {
+    let mut groups = Vec::new();
+    let mut report = run_streaming(options, roots, |group| {
+        groups.push(group);
+        Ok(())
+    })?;
+    report.groups = groups;
+    Ok(report)
+}
+
+/*
+ * Run the comparison and action, handing every group to `emit` as soon as it is final
+ * (after the action was applied on it). The groups of the returned report stay empty.
+ */
+pub fn run_streaming<F>(options: &Options, roots: &[PathBuf], mut emit: F) -> Result<Report, Error>
+where F: FnMut(DuplicateGroup) -> Result<(), Error>,
+{
+    let start = Instant::now();
+    let deadline = Deadline::new(options.timeout);
+    let mut throttle = Throttle::new(options.max_read_rate);
+    let counters = WalkCounters::default();
+    let iter = counted_files(options, roots, &deadline, &counters);
+    let mut log = match options.action_log.as_ref() {
+        Some(path) => Some(ActionLog::open(path)?),
+        None => None,
+    };
+    let mut manifest = match options.checksum_output.as_ref() {
+        Some(path) if options.mode == Mode::Hashes => Some(Manifest::create(path)?),
+        _ => None,
+    };
+    let mut steps = Vec::new();
+    let mut action_time = Duration::default();
+    let mut found = (0, 0, 0);
+    let mut process = |mut group: DuplicateGroup| -> Result<(), Error> {
+        //The walk was cut short, groups of the files seen so far mean nothing
+        if counters.capped.get() {
+            return Ok(());
+        }
+        if options.exclude_groups.iter().any(|p| p.matches(&group)) {
+            return Ok(());
+        }
+        if options.relative_to_roots {
+            group.annotate_roots(roots);
+        }
+        if options.ownership != OwnershipCheck::Ignore {
+            group.annotate_ownership();
+            if options.ownership == OwnershipCheck::Differing && !group.ownership_differs() {
+                return Ok(());
+            }
+        }
+        //Shown in report mode too, so the plan can be checked before acting
+        if options.mode == Mode::Hashes && !options.hash.unique {
+            group.survivor = action::survivor(&Disk, &group, options.keep, &options.keep_in)?;
+        }
+        if let Action::Reflink { dry_run } | Action::Trash { dry_run } = options.action {
+            let action_start = Instant::now();
+            for mut step in action::plan(&Disk, &group, options.keep, &options.keep_in)?.into_iter() {
+                step.risk = options.risks.reason(&Disk, &step.target)?;
+                if !dry_run && (step.risk.is_none() || options.include_risky) {
+                    match options.action {
+                        Action::Trash { .. } => action::trash(&Disk, &step)?,
+                        _ => action::reflink(&Disk, &step)?,
+                    }
+                    if let Some(log) = log.as_mut() {
+                        log.record(options.action.name(), &step)?;
+                    }
+                }
+                steps.push(step);
+            }
+            action_time += action_start.elapsed();
+        }
+        let redundant = group.files.len().saturating_sub(1) as u64;
+        found.0 += 1;
+        found.1 += redundant;
+        found.2 += redundant * group.size.unwrap_or(0);
+        emit(group)
+    };
+    let mut handle = |group: DuplicateGroup| -> Result<(), Error> {
+        for group in xattrs::split(group, &options.match_xattr).into_iter() {
+            process(group)?;
+        }
+        Ok(())
+    };
+    match options.mode {
+        Mode::Names if options.two_pass => find::for_each_by_repeated_names(first_pass(options, roots, &deadline, &counters), iter, options.normalize_unicode, &mut handle)?,
+        Mode::Names => find::for_each_by_names(iter, options.normalize_unicode, &mut handle)?,
+        Mode::NamesSizes => find::for_each_by_names_sizes(iter, options.normalize_unicode, &mut handle)?,
+        Mode::Confirmed => find::for_each_confirmed(iter, options.normalize_unicode, &mut throttle, &deadline, &mut handle)?,
+        Mode::Hashes => match (options.index.as_ref(), options.memory_limit) {
+            (Some(path), _) => index::for_each_by_hashes(iter, &mut Index::open(path, options.cache_policy)?, options.hash, manifest.as_mut(), &mut throttle, &deadline, &mut handle)?,
+            (None, Some(limit)) => {
+                let mut iter = iter;
+                match within_memory(&mut iter, limit) {
+                    Ok(files) => hashes_in_memory(files, options, manifest.as_mut(), &mut throttle, &deadline, &mut handle)?,
+                    Err(files) => {
+                        let mut spill = TemporaryIndex::create()?;
+                        index::for_each_by_hashes(files.into_iter().chain(iter), spill.index(), options.hash, manifest.as_mut(), &mut throttle, &deadline, &mut handle)?
+                    },
+                }
+            },
+            (None, None) => hashes_in_memory(iter, options, manifest.as_mut(), &mut throttle, &deadline, &mut handle)?,
+        },
+        Mode::FuzzyBytes(max_diff) => find::for_each_by_fuzzy_bytes(iter, max_diff, options.hash, &mut throttle, &deadline, &mut handle)?,
+        Mode::Ends(bytes) => find::for_each_by_ends(iter, bytes, options.hash, &mut throttle, &deadline, &mut handle)?,
+        Mode::SkipHeader(bytes) => find::for_each_by_payload(iter, bytes, options.hash, &mut throttle, &deadline, &mut handle)?,
+        Mode::Range { start, len } => find::for_each_by_range(iter, start, len, options.hash, &mut throttle, &deadline, &mut handle)?,
+        Mode::MediaStreams => media::for_each_by_streams(iter, options.hash, &mut throttle, &deadline, &mut handle)?,
+        Mode::Unpadded => find::for_each_by_unpadded(iter, options.hash, &mut throttle, &deadline, &mut handle)?,
+        Mode::Command(ref command) => command::for_each_by_command(iter, command, options.hash, &mut throttle, &deadline, &mut handle)?,
+        Mode::Directories => directories::for_each_with_directories(iter, options.hash, &mut throttle, &deadline, &mut handle)?,
+        Mode::Matching(ref wanted) => find::for_each_matching(iter, wanted, options.hash, &mut throttle, &deadline, &mut handle)?,
+        Mode::Decompressed => decompress::for_each_by_decompressed(iter, options.hash, &mut throttle, &deadline, &mut handle)?,
+        Mode::Truncated => find::for_each_truncated(iter, options.normalize_unicode, &mut throttle, &deadline, &mut handle)?,
+        Mode::Chunks { average, min_overlap } => chunked::for_each_overlap(iter, average, min_overlap, &mut throttle, &deadline, &mut handle)?,
+    }
+    if let Some(manifest) = manifest {
+        manifest.finish()?;
+    }
+    let end = Instant::now();
+    //A timeout can stop a comparison before the walk ran dry
+    let walk_end = counters.end.get().unwrap_or(end);
+    Ok(Report {
+        mode: String::from(options.mode.name()),
+        algorithm: options.mode.algorithm().map(String::from),
+        groups: Vec::new(),
+        steps,
+        timed_out: deadline.expired() && !counters.capped.get(),
+        capped: counters.capped.get(),
+        stats: Stats {
+            files_scanned: counters.scanned.get(),
+            files_skipped: counters.skipped.get(),
+            errors: counters.errors.get(),
+            bytes_hashed: throttle.bytes(),
+            files_retried: throttle.retried(),
+            groups: found.0,
+            redundant_files: found.1,
+            reclaimable_bytes: found.2,
+            walk_time: walk_end - start,
+            compare_time: (end - walk_end).saturating_sub(action_time),
+            action_time,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn write(path: &Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "a").unwrap();
+    }
+
+    fn walked(root: &Path) -> Vec<PathBuf> {
+        let filters = Filters::default();
+        let deadline = Deadline::default();
+        let roots = [root.to_path_buf()];
+        let mut walked : Vec<PathBuf> = files(&filters, &roots, &deadline).map(DirEntry::into_path).collect();
+        walked.sort();
+        walked
+    }
+
+    #[test]
+    fn hidden_root_is_walked() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join(".hidden");
+        write(&root.join("a"));
+        assert_eq!(walked(&root), vec![root.join("a")]);
+    }
+
+    #[test]
+    fn hidden_directory_under_a_root_is_walked() {
+        let dir = tempfile::tempdir().unwrap();
+        write(&dir.path().join("a"));
+        write(&dir.path().join(".hidden/a"));
+        assert_eq!(walked(dir.path()), vec![dir.path().join(".hidden/a"), dir.path().join("a")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_root_is_walked() {
+        let dir = tempfile::tempdir().unwrap();
+        write(&dir.path().join("backup-3/a"));
+        write(&dir.path().join("backup-3/sub/b"));
+        let latest = dir.path().join("latest");
+        std::os::unix::fs::symlink(dir.path().join("backup-3"), &latest).unwrap();
+        assert_eq!(walked(&latest), vec![latest.join("a"), latest.join("sub/b")]);
+    }
+}
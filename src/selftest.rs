@@ -0,0 +1,117 @@
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use sha2::{Digest, Sha256};
+use crate::find;
+use crate::hash::{self, Hash, BUFFER_SIZE};
+use crate::throttle::Throttle;
+
+//Sizes of the generated files: empty, around the read buffer and its multiples, around the BufReader one
+const SIZES: &[usize] = &[
+    0, 1, 2,
+    BUFFER_SIZE - 1, BUFFER_SIZE, BUFFER_SIZE + 1,
+    2 * BUFFER_SIZE - 1, 2 * BUFFER_SIZE, 2 * BUFFER_SIZE + 1,
+    3 * BUFFER_SIZE + 17,
+    8191, 8192, 8193,
+    100_000,
+];
+
+//Bytes of each end for hash_ends, not a multiple of the buffer so the chain of both ends reads short
+const ENDS: u64 = 700;
+
+//Digests of the sha256 specification (FIPS 180-2), checking the hash itself before the reading
+const KNOWN: &[(&[u8], &str)] = &[
+    (b"", "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+    (b"abc", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+    (b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq", "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"),
+];
+
+//One hashing of one generated content, along the digest it ought to give
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    pub name: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl Check {
+    fn new(name: String, expected: &[u8], actual: &Hash) -> Check {
+        Check { name, expected: hex(expected), actual: format!("{:x}", actual) }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.expected == self.actual
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+//Never a zero, so the content doesn't end with the padding hash_unpadded strips
+fn content(size: usize) -> Vec<u8> {
+    (0..size).map(|i| (i % 255) as u8 + 1).collect()
+}
+
+/*
+ * Hash generated files of known content with every hashing function of the hash module
+ * and compare the digests with the ones of the same bytes hashed at once in memory,
+ * which goes through none of the reading loops. The files are written in a directory
+ * of their own under the temporary directory, removed at the end.
+ */
+pub fn run() -> Result<Vec<Check>, Error> {
+    //Of a random name, created by this run only
+    let directory = tempfile::Builder::new().prefix("rustadup-selftest-").tempdir()?;
+    let checks = checks(directory.path());
+    directory.close()?;
+    checks
+}
+
+fn checks(directory: &Path) -> Result<Vec<Check>, Error> {
+    let mut throttle = Throttle::new(None);
+    let mut checks = Vec::new();
+    for (input, expected) in KNOWN.iter() {
+        let actual = Sha256::digest(input);
+        checks.push(Check { name: format!("sha256 of {:?}", String::from_utf8_lossy(input)), expected: String::from(*expected), actual: hex(&actual) });
+    }
+    for &size in SIZES.iter() {
+        let content = content(size);
+        let path: PathBuf = directory.join(format!("{}.bin", size));
+        fs::write(&path, &content)?;
+
+        let actual = hash::hash_file(&path, &mut throttle)?;
+        checks.push(Check::new(format!("hash_file of {} bytes", size), &Sha256::digest(&content), &actual));
+
+        let (actual, bad) = hash::hash_file_tolerant(&path, &mut throttle)?;
+        let name = format!("hash_file_tolerant of {} bytes", size);
+        checks.push(if bad.is_empty() {
+            Check::new(name, &Sha256::digest(&content), &actual)
+        } else {
+            Check { name, expected: String::from("no unreadable region"), actual: format!("{} unreadable regions", bad.len()) }
+        });
+
+        let ends = ENDS as usize;
+        let expected = if size <= 2 * ends {
+            Sha256::digest(&content)
+        } else {
+            Sha256::digest(&[&content[..ends], &content[size - ends..]].concat())
+        };
+        let actual = hash::hash_ends(&path, ENDS, &mut throttle)?;
+        checks.push(Check::new(format!("hash_ends of {} bytes", size), &expected, &actual));
+
+        let offset = size / 3;
+        let actual = hash::hash_from(&path, offset as u64, &mut throttle)?;
+        checks.push(Check::new(format!("hash_from {} of {} bytes", offset, size), &Sha256::digest(&content[offset..]), &actual));
+
+        let start = size / 4;
+        let end = size.min(start + BUFFER_SIZE + 3);
+        let actual = hash::hash_range(&path, start as u64, (BUFFER_SIZE + 3) as u64, &mut throttle)?;
+        checks.push(Check::new(format!("hash_range {}..{} of {} bytes", start, end, size), &Sha256::digest(&content[start..end]), &actual));
+
+        let padded = directory.join(format!("{}-padded.bin", size));
+        fs::write(&padded, [&content[..], &[0u8; BUFFER_SIZE + 5][..]].concat())?;
+        let actual = hash::hash_unpadded(&padded, find::PADDING_LIMIT, &mut throttle)?;
+        checks.push(Check::new(format!("hash_unpadded of {} bytes and {} zeros", size, BUFFER_SIZE + 5), &Sha256::digest(&content), &actual));
+    }
+    Ok(checks)
+}
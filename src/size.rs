@@ -0,0 +1,52 @@
+/*
+ * Parsing of human readable sizes such as "512", "4K", "10M" or "2Gb".
+ * Units are powers of 1024, like the size constants used for hash skipping.
+ */
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number : u64 = number.parse().map_err(|_| format!("invalid size '{}'", value))?;
+    let multiplier : u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1024,
+        "m" | "mb" | "mib" => 1024 * 1024,
+        "g" | "gb" | "gib" => 1024 * 1024 * 1024,
+        "t" | "tb" | "tib" => 1024 * 1024 * 1024 * 1024,
+        _ => return Err(format!("unknown size unit in '{}'", value)),
+    };
+    number.checked_mul(multiplier).ok_or_else(|| format!("size '{}' is too big", value))
+}
+
+pub fn is_size(value: String) -> Result<(), String> {
+    parse_size(&value).map(|_| ())
+}
+
+//Byte range given as START:LEN, both sizes, e.g. "4K:32"
+pub fn parse_range(value: &str) -> Result<(u64, u64), String> {
+    let (start, len) = value.split_once(':').ok_or_else(|| format!("invalid range '{}', expected START:LEN", value))?;
+    let (start, len) = (parse_size(start)?, parse_size(len)?);
+    if len == 0 {
+        return Err(format!("range '{}' is empty", value));
+    }
+    Ok((start, len))
+}
+
+pub fn is_range(value: String) -> Result<(), String> {
+    parse_range(&value).map(|_| ())
+}
+
+//Human readable size with one decimal in the largest fitting unit, e.g. "1.5M"
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["K", "M", "G", "T"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", value, UNITS[unit])
+}
@@ -0,0 +1,128 @@
+use std::path::Path;
+use crate::group::DuplicateGroup;
+
+//Placeholders of a --template, along what they stand for
+pub const PLACEHOLDERS: &[&str] = &["path", "relpath", "name", "size", "hash", "group", "id"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Piece {
+    Text(String),
+    //the member as found by the walk
+    Path,
+    //the member under the root it was found in
+    RelPath,
+    //name compared of the group
+    Name,
+    //size of the group, empty when it has none
+    Size,
+    //hash of the group, empty when it has none
+    Hash,
+    //number of the group in the output, from 1
+    Group,
+    //identifier of the group, the same from one run to the other (see DuplicateGroup::stable_id)
+    Id,
+}
+
+/*
+ * Line printed for every member of the groups, such as `{group}\t{size}\t{path}`.
+ * `{{` and `}}` stand for the braces themselves. Parsing fails on anything else
+ * between braces, so a typo is caught before the scan rather than printed as is.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+impl Template {
+    pub fn parse(spec: &str) -> Result<Template, String> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut chars = spec.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                },
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                },
+                '{' => {
+                    let mut name = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break
+                        }
+                        name.push(c);
+                    }
+                    if !closed {
+                        return Err(format!("unclosed {{{}, write {{{{ for a brace", name));
+                    }
+                    let piece = match name.as_str() {
+                        "path" => Piece::Path,
+                        "relpath" => Piece::RelPath,
+                        "name" => Piece::Name,
+                        "size" => Piece::Size,
+                        "hash" => Piece::Hash,
+                        "group" => Piece::Group,
+                        "id" => Piece::Id,
+                        _ => return Err(format!("unknown placeholder {{{}}}, expected one of {}", name, PLACEHOLDERS.iter().map(|p| format!("{{{}}}", p)).collect::<Vec<_>>().join(", "))),
+                    };
+                    if !text.is_empty() {
+                        pieces.push(Piece::Text(std::mem::take(&mut text)));
+                    }
+                    pieces.push(piece);
+                },
+                '}' => return Err(String::from("unmatched }, write }} for a brace")),
+                _ => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        Ok(Template { pieces })
+    }
+
+    //The line of one member of the `number`th group, without its end
+    pub fn render(&self, number: usize, group: &DuplicateGroup, file: &Path, relpath: &Path) -> String {
+        let mut line = String::new();
+        for piece in self.pieces.iter() {
+            match piece {
+                Piece::Text(text) => line.push_str(text),
+                Piece::Path => line.push_str(&file.to_string_lossy()),
+                Piece::RelPath => line.push_str(&relpath.to_string_lossy()),
+                Piece::Name => line.push_str(&group.name),
+                Piece::Size => line.push_str(&group.size.map(|s| s.to_string()).unwrap_or_default()),
+                Piece::Hash => line.push_str(group.hash.as_deref().unwrap_or_default()),
+                Piece::Group => line.push_str(&number.to_string()),
+                Piece::Id => line.push_str(&group.stable_id()),
+            }
+        }
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn placeholders_and_braces() {
+        let template = Template::parse("{{{group}}}\t{size}\t{relpath}").unwrap();
+        let group = DuplicateGroup::new(String::from("a"), Some(3), None, vec![PathBuf::from("x/a")]);
+        assert_eq!(template.render(2, &group, Path::new("x/a"), Path::new("a")), "{2}\t3\ta");
+        assert_eq!(Template::parse("{hash}").unwrap().render(1, &group, Path::new("x/a"), Path::new("a")), "");
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Template::parse("{paht}").unwrap_err().starts_with("unknown placeholder {paht}"));
+        assert!(Template::parse("{path").unwrap_err().starts_with("unclosed {path"));
+        assert!(Template::parse("a}b").unwrap_err().starts_with("unmatched }"));
+        assert!(Template::parse("{}").unwrap_err().starts_with("unknown placeholder {}"));
+    }
+}
@@ -0,0 +1,78 @@
+use std::io::{Error, Read};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/*
+ * Approximate limit of the read throughput: every read is accounted and when the
+ * bytes read so far are ahead of the allowed rate, the caller sleeps until it catches up.
+ * A single throttle is shared by the whole scan so the limit applies to the total,
+ * which also makes it the place counting the bytes read, limited or not, and the files
+ * whose reads had to be retried.
+ */
+pub struct Throttle {
+    rate: Option<u64>,
+    start: Instant,
+    bytes: u64,
+    retried: u64,
+}
+
+impl Throttle {
+    pub fn new(rate: Option<u64>) -> Throttle {
+        Throttle {
+            rate: rate.filter(|r| *r > 0),
+            start: Instant::now(),
+            bytes: 0,
+            retried: 0,
+        }
+    }
+
+    pub fn consume(&mut self, n: usize) {
+        self.bytes += n as u64;
+        let rate = match self.rate {
+            Some(rate) => rate,
+            None => return,
+        };
+        let expected = Duration::from_secs_f64(self.bytes as f64 / rate as f64);
+        let elapsed = self.start.elapsed();
+        if expected > elapsed {
+            thread::sleep(expected - elapsed);
+        }
+    }
+
+    //Whether reads have to go through consume to be limited
+    pub fn limited(&self) -> bool {
+        self.rate.is_some()
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn retried(&self) -> u64 {
+        self.retried
+    }
+
+    pub(crate) fn count_retried(&mut self) {
+        self.retried += 1;
+    }
+}
+
+//Reader accounting everything it reads in a throttle
+pub struct ThrottledReader<'a, R> {
+    inner: R,
+    throttle: &'a mut Throttle,
+}
+
+impl<'a, R: Read> ThrottledReader<'a, R> {
+    pub fn new(inner: R, throttle: &'a mut Throttle) -> ThrottledReader<'a, R> {
+        ThrottledReader { inner, throttle }
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<'_, R> {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        let n = self.inner.read(buffer)?;
+        self.throttle.consume(n);
+        Ok(n)
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{Error, Write};
+use std::path::Component;
+use crate::group::DuplicateGroup;
+use crate::size::format_size;
+
+const BOLD: &str = "\x1b[1m";
+const YELLOW: &str = "\x1b[33m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+//A member of a group, placed in its directory
+struct TreeFile {
+    name: OsString,
+    //space freed by removing it, nothing for the copy kept
+    reclaimable: u64,
+    kept: bool,
+}
+
+#[derive(Default)]
+struct TreeNode {
+    children: BTreeMap<OsString, TreeNode>,
+    files: Vec<TreeFile>,
+    //of the files of this directory and all its subdirectories
+    reclaimable: u64,
+}
+
+impl TreeNode {
+    fn insert(&mut self, components: &[OsString], file: TreeFile) {
+        self.reclaimable += file.reclaimable;
+        match components.split_first() {
+            Some((first, rest)) => self.children.entry(first.clone()).or_default().insert(rest, file),
+            None => self.files.push(file),
+        }
+    }
+}
+
+/*
+ * Directory hierarchy holding the members of the groups, like `du` would show it:
+ * every directory has the space its duplicates would free, the survivor of each group
+ * (its first member when it has none) being the copy kept. Directories without duplicates don't appear and chains of
+ * directories with a single subdirectory are shown on one line.
+ */
+pub fn write_tree<W: Write>(out: &mut W, groups: &[DuplicateGroup], color: bool) -> Result<(), Error> {
+    let mut root = TreeNode::default();
+    for group in groups.iter() {
+        let kept = group.survivor.as_ref().unwrap_or(&group.files[0]);
+        for f in group.files.iter() {
+            let size = match group.size {
+                Some(size) => size,
+                None => fs::symlink_metadata(f).map(|m| m.len()).unwrap_or(0),
+            };
+            let components : Vec<OsString> = f.parent().into_iter()
+                .flat_map(|p| p.components())
+                .map(|c| match c {
+                    Component::RootDir => OsString::from("/"),
+                    c => c.as_os_str().to_os_string(),
+                })
+                .collect();
+            let file = TreeFile {
+                name: f.file_name().map(|n| n.to_os_string()).unwrap_or_default(),
+                reclaimable: if f == kept { 0 } else { size },
+                kept: f == kept,
+            };
+            root.insert(&components, file);
+        }
+    }
+    let style = |code: &'static str| if color { code } else { "" };
+    writeln!(out, "{}{} reclaimable{}", style(BOLD), format_size(root.reclaimable), style(RESET))?;
+    write_children(out, &root, "", &style)
+}
+
+fn write_children<W, S>(out: &mut W, node: &TreeNode, prefix: &str, style: &S) -> Result<(), Error>
+where W: Write,
+      S: Fn(&'static str) -> &'static str,
+{
+    let count = node.children.len() + node.files.len();
+    let mut index = 0;
+    for (name, mut child) in node.children.iter() {
+        index += 1;
+        let (branch, indent) = if index == count { ("└── ", "    ") } else { ("├── ", "│   ") };
+        //Walk down the chain of lone subdirectories
+        let mut label = name.to_string_lossy().into_owned();
+        while child.files.is_empty() && child.children.len() == 1 {
+            let (name, next) = child.children.iter().next().unwrap();
+            if !label.ends_with('/') {
+                label.push('/');
+            }
+            label.push_str(&name.to_string_lossy());
+            child = next;
+        }
+        writeln!(out, "{}{}{}{}{} ({} reclaimable)", prefix, branch, style(BOLD), label, style(RESET), format_size(child.reclaimable))?;
+        write_children(out, child, &format!("{}{}", prefix, indent), style)?;
+    }
+    for file in node.files.iter() {
+        index += 1;
+        let branch = if index == count { "└── " } else { "├── " };
+        if file.kept {
+            writeln!(out, "{}{}{}{} (kept){}", prefix, branch, style(DIM), file.name.to_string_lossy(), style(RESET))?;
+        } else {
+            writeln!(out, "{}{}{}{}{} ({})", prefix, branch, style(YELLOW), file.name.to_string_lossy(), style(RESET), format_size(file.reclaimable))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use std::io::Error;
+use std::path::PathBuf;
+use walkdir::DirEntry;
+use crate::find;
+
+/*
+ * Extensions a sniffed type is also right under, besides the one infer names it by:
+ * spellings of the same format, and formats built on another one, whose files infer
+ * only knows by the container (a .docx is sniffed as docx, a .jar as zip).
+ */
+const ALSO_DECLARED: &[(&str, &[&str])] = &[
+    ("jpg", &["jpeg", "jpe", "jfif"]),
+    ("tif", &["tiff", "dng", "nef", "arw"]),
+    ("mpg", &["mpeg", "vob"]),
+    ("midi", &["mid"]),
+    ("aiff", &["aif"]),
+    ("gz", &["tgz"]),
+    ("mp4", &["m4v", "m4a", "m4b", "3gp"]),
+    ("mkv", &["mka", "mks"]),
+    ("ogg", &["oga", "ogv", "ogx"]),
+    ("elf", &["so", "o"]),
+    ("exe", &["dll", "sys", "scr"]),
+    ("dll", &["exe", "sys"]),
+    ("zip", &["jar", "apk", "docx", "xlsx", "pptx", "odt", "ods", "odp", "epub", "xpi", "whl", "ipa", "cbz", "kmz", "nupkg"]),
+];
+
+//A file whose content is of another type than its extension says
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub path: PathBuf,
+    //extension of the type sniffed, as infer names it
+    pub detected: &'static str,
+    pub mime: &'static str,
+    //last extension of the name, lowercased
+    pub declared: String,
+}
+
+fn declarable(extension: &str) -> bool {
+    infer::is_supported(extension) || ALSO_DECLARED.iter().any(|(_, also)| also.contains(&extension))
+}
+
+fn agrees(detected: &str, declared: &str) -> bool {
+    detected == declared || ALSO_DECLARED.iter().any(|(d, also)| *d == detected && also.contains(&declared))
+}
+
+/*
+ * Sniff the magic bytes of the file and compare its type with its extension.
+ * Only extensions of types infer can recognize are checked: a .txt or a .py can't be
+ * told wrong, nor can a file whose content isn't recognized (possibly damaged).
+ * Text types (html, xml, scripts) are guessed rather than sniffed and never reported.
+ */
+pub fn check(entry: &DirEntry) -> Result<Option<Mismatch>, Error> {
+    let name = entry.file_name().to_string_lossy();
+    let declared = match find::extension(&name) {
+        Some(extension) => String::from(extension.rsplit('.').next().unwrap_or_default()),
+        None => return Ok(None),
+    };
+    if !declarable(&declared) {
+        return Ok(None);
+    }
+    let detected = match find::unless_vanished(entry.path(), infer::get_from_path(entry.path()))?.flatten() {
+        Some(detected) if detected.matcher_type() != infer::MatcherType::Text => detected,
+        _ => return Ok(None),
+    };
+    if agrees(detected.extension(), &declared) {
+        return Ok(None);
+    }
+    Ok(Some(Mismatch {
+        path: entry.path().to_path_buf(),
+        detected: detected.extension(),
+        mime: detected.mime_type(),
+        declared,
+    }))
+}
@@ -0,0 +1,66 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use crate::decompress;
+use crate::group::DuplicateGroup;
+use crate::hash;
+use crate::output::Document;
+use crate::throttle::Throttle;
+
+//Reason why a file of a saved group doesn't match the group anymore
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mismatch {
+    Missing,
+    Size(u64),
+    Hash(String),
+}
+
+/*
+ * Check that a group of a saved report still holds: every file still exists and,
+ * when the report recorded them, still has the same size and sha256 hash.
+ * Sizes of `n --truncated` groups are the one of the complete copy only and aren't checked.
+ * Groups of `h --decompress` are checked on the decompressed content, size included.
+ */
+pub fn verify_group(document: &Document, group: &DuplicateGroup, throttle: &mut Throttle) -> Result<Vec<(PathBuf, Mismatch)>, Error> {
+    let check_hash = document.algorithm.as_deref() == Some("sha256");
+    let decompressed = document.algorithm.as_deref() == Some("sha256-decompressed");
+    let mut mismatches = Vec::new();
+    for file in group.files.iter() {
+        if decompressed {
+            match decompress::hash_file(file, throttle) {
+                Ok((current, size)) => {
+                    if group.size.is_some_and(|s| s != size) {
+                        mismatches.push((file.clone(), Mismatch::Size(size)));
+                    } else if group.hash.as_ref().is_some_and(|h| h != &format!("{:x}", current)) {
+                        mismatches.push((file.clone(), Mismatch::Hash(format!("{:x}", current))));
+                    }
+                },
+                Err(e) if e.kind() == ErrorKind::NotFound => mismatches.push((file.clone(), Mismatch::Missing)),
+                Err(e) => return Err(e),
+            }
+            continue
+        }
+        let metadata = match fs::metadata(file) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                mismatches.push((file.clone(), Mismatch::Missing));
+                continue
+            },
+            Err(e) => return Err(e),
+        };
+        //The size of a directory group is the one of the files under each member
+        if let Some(size) = group.size.filter(|_| group.truncated.is_none() && group.directory_files.is_none()) {
+            if metadata.len() != size {
+                mismatches.push((file.clone(), Mismatch::Size(metadata.len())));
+                continue
+            }
+        }
+        if let Some(recorded) = group.hash.as_ref().filter(|_| check_hash) {
+            let current = format!("{:x}", hash::hash_file(file, throttle)?);
+            if &current != recorded {
+                mismatches.push((file.clone(), Mismatch::Hash(current)));
+            }
+        }
+    }
+    Ok(mismatches)
+}
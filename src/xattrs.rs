@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+use std::path::Path;
+use crate::group::DuplicateGroup;
+
+//Values of the attributes, None for the ones missing or unreadable
+fn values(path: &Path, names: &[String]) -> Vec<Option<Vec<u8>>> {
+    names.iter().map(|n| xattr::get(path, n).ok().flatten()).collect()
+}
+
+/*
+ * Split a group on the values of the selected extended attributes, as if they were part
+ * of the grouping key: members end up together only when every attribute is the same or
+ * missing on all of them. Members left alone are dropped, a group that was a single file
+ * (unique files) is kept as is.
+ * Where extended attributes aren't supported every value is missing and nothing is split.
+ */
+pub fn split(group: DuplicateGroup, names: &[String]) -> Vec<DuplicateGroup> {
+    if names.is_empty() || group.files.len() < 2 {
+        return vec![group];
+    }
+    let mut classes : Vec<Vec<usize>> = Vec::new();
+    let mut index : HashMap<Vec<Option<Vec<u8>>>, usize> = HashMap::new();
+    for (i, f) in group.files.iter().enumerate() {
+        let class = *index.entry(values(f, names)).or_insert_with(|| {
+            classes.push(Vec::new());
+            classes.len() - 1
+        });
+        classes[class].push(i);
+    }
+    if classes.len() == 1 {
+        return vec![group];
+    }
+    classes.into_iter().filter(|c| c.len() > 1).map(|members| {
+        let mut split = group.clone();
+        split.files = members.iter().map(|i| group.files[*i].clone()).collect();
+        split.truncated = group.truncated.clone().filter(|t| split.files.contains(t));
+        split.compressed = group.compressed.iter().filter(|c| split.files.contains(c)).cloned().collect();
+        split.ownership = group.ownership.as_ref().map(|o| members.iter().map(|i| o[*i]).collect());
+        split.sources = group.sources.as_ref().map(|s| members.iter().map(|i| s[*i].clone()).collect());
+        split.split = true;
+        split
+    }).collect()
+}
@@ -0,0 +1,118 @@
+use std::fs;
+use std::path::Path;
+use assert_cmd::Command;
+use tempfile::TempDir;
+
+//Two copies of a under x and y, and b alone
+fn tree() -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    for (path, content) in [("x/a", "same"), ("y/a", "same"), ("y/b", "alone")] {
+        let path = dir.path().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    dir
+}
+
+fn rustadup(dir: &Path) -> Command {
+    let mut command = Command::cargo_bin("rustadup").unwrap();
+    command.current_dir(dir).env_remove("RUSTADUP_FORMAT").env_remove("RUSTADUP_TIMEOUT");
+    command
+}
+
+#[test]
+fn groups_found() {
+    let dir = tree();
+    rustadup(dir.path()).args(["h", "."]).assert().code(0);
+}
+
+#[test]
+fn timeout() {
+    let dir = tree();
+    rustadup(dir.path()).args(["--timeout", "0s", "h", "."]).assert().code(3);
+}
+
+#[test]
+fn verify_failed() {
+    let dir = tree();
+    let report = rustadup(dir.path()).args(["--format", "json", "h", "."]).assert().code(0).get_output().stdout.clone();
+    fs::write(dir.path().join("report.json"), &report).unwrap();
+    fs::write(dir.path().join("report.json.zst"), zstd::encode_all(&report[..], 3).unwrap()).unwrap();
+    rustadup(dir.path()).args(["verify", "report.json"]).assert().code(0);
+    rustadup(dir.path()).args(["verify", "report.json.zst"]).assert().code(0);
+    fs::write(dir.path().join("y/a"), "changed").unwrap();
+    rustadup(dir.path()).args(["verify", "report.json"]).assert().code(4);
+    rustadup(dir.path()).args(["verify", "report.json.zst"]).assert().code(4);
+}
+
+#[test]
+fn no_groups() {
+    let dir = tree();
+    rustadup(dir.path()).args(["h", "y"]).assert().code(5);
+    rustadup(dir.path()).args(["--quiet", "h", "y"]).assert().code(5).stderr("");
+}
+
+#[test]
+fn max_files() {
+    let dir = tree();
+    rustadup(dir.path()).args(["--max-files", "2", "h", "."]).assert().code(6).stdout("");
+    rustadup(dir.path()).args(["--max-files", "3", "h", "."]).assert().code(0);
+    rustadup(dir.path()).args(["--max-files", "2", "n", "--two-pass", "."]).assert().code(6).stdout("");
+}
+
+//A failing digest gives 7, which a correct build never does
+#[test]
+fn selftest() {
+    let dir = tree();
+    rustadup(dir.path()).arg("selftest").assert().code(0);
+}
+
+#[test]
+fn error() {
+    let dir = tree();
+    rustadup(dir.path()).args(["verify", "missing.json"]).assert().code(1);
+}
+
+#[test]
+fn action_values() {
+    let dir = tree();
+    rustadup(dir.path()).args(["h", "--action", "delete", "."]).assert().code(1);
+    rustadup(dir.path()).args(["h", "--action", "trash", "--keep", "newest", "."]).assert().code(1);
+}
+
+#[test]
+fn action_conflicts() {
+    let dir = tree();
+    rustadup(dir.path()).args(["h", "--action", "trash", "--ends", "16", "."]).assert().code(1);
+    rustadup(dir.path()).args(["h", "--action", "reflink", "--unique", "."]).assert().code(1);
+    rustadup(dir.path()).args(["h", "--dry-run", "."]).assert().code(1);
+    rustadup(dir.path()).args(["h", "--action", "report", "--dry-run", "."]).assert().code(1).stderr("--dry-run needs an --action changing files\n");
+    rustadup(dir.path()).args(["h", "--action", "report", "--action-log", "log", "."]).assert().code(1);
+}
+
+#[test]
+fn action_dry_run() {
+    let dir = tree();
+    for action in ["reflink", "trash"] {
+        let run = rustadup(dir.path()).args(["h", "--action", action, "--dry-run", "--risky-age", "0s", "."]).assert().code(0);
+        let stderr = String::from_utf8_lossy(&run.get_output().stderr).into_owned();
+        assert!(stderr.contains(&format!("would {} 1 files", action)), "{}", stderr);
+    }
+    assert_eq!(fs::read_to_string(dir.path().join("y/a")).unwrap(), "same");
+    assert!(!dir.path().join(".rustadup.lock").exists());
+}
+
+#[test]
+fn streams() {
+    let dir = tree();
+    for format in ["json-stream", "json-array"] {
+        rustadup(dir.path()).args(["--format", format, "--count", "groups", "h", "."]).assert().code(0).stdout("1\n");
+        let top = rustadup(dir.path()).args(["--format", format, "h", "--top", "1", "."]).assert().code(0).get_output().stdout.clone();
+        assert!(!String::from_utf8_lossy(&top).contains('{'));
+    }
+    rustadup(dir.path()).args(["--format", "json-stream", "--max-files", "1", "h", "."]).assert().code(6)
+        .stdout(predicates::str::ends_with("{\"complete\":false}\n"));
+    let capped = rustadup(dir.path()).args(["--format", "json-array", "--max-files", "1", "h", "."]).assert().code(6).get_output().stdout.clone();
+    let document : serde_json::Value = serde_json::from_slice(&capped).unwrap();
+    assert_eq!(document["complete"], serde_json::Value::Bool(false));
+}